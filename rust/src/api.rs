@@ -0,0 +1,241 @@
+//! REST API of the current readings, on the embedded HTTP server:
+//! - `GET /meters`: the meters whose name is known
+//! - `GET /meters/<id>`: the last frame of a meter, as written by the JSON lines output
+//! - `GET /meters/<id>/labels/<label>`: one label or derived metric of the last frame
+//! - `GET /meters/<id>/history?label=<label>&from=<time>&to=<time>`: the minutes of a label stored by the SQLite output
+//! - `GET /health`: status of the inputs and outputs, 503 if any is unhealthy
+//! - `GET /metrics`: the Prometheus exposition
+//! - `GET /stream`: the live stream of the frames, see `stream`
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tic2mqtt::derived::metric_info;
+use tic2mqtt::utils::{format_timestamp, json_escape, parse_timestamp};
+
+use crate::http::{self, Request, Response};
+use crate::json;
+use crate::meter::Meter;
+use crate::mqtt::METER_TIMEOUT;
+use crate::output::{OutputStats, QUEUE_SIZE};
+use crate::prometheus;
+use crate::sqlite;
+use crate::stats::MqttStats;
+use crate::stream::{self, Subscribers};
+
+/// What the API reads from
+pub struct Api {
+    pub meters: Vec<Meter>,
+    /// Statistics of the MQTT output, none if not enabled
+    pub mqtt: Option<Arc<MqttStats>>,
+    pub outputs: Vec<Arc<OutputStats>>,
+    /// Clients of the live stream
+    pub subscribers: Subscribers,
+    /// Start of the bridge, the age of the last frame before the first one
+    pub started: Instant,
+    /// Database of the SQLite output, if enabled, where the history is read
+    pub history: Option<String>,
+}
+
+/// Default period of a history request
+const HISTORY_PERIOD: Duration = Duration::from_secs(24 * 3600);
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(status, format!("{{\"error\": \"{}\"}}\n", json_escape(message)))
+}
+
+impl Api {
+    fn meter(&self, id: &str) -> Option<&Meter> {
+        self.meters.iter().find(|m| !id.is_empty() && m.name() == id)
+    }
+
+    /// [{"id": ..., "mode": ..., "frames": ..., "timestamp": <last frame or null>}, ...]
+    fn meters(&self) -> Response {
+        let meters: Vec<String> = self.meters.iter()
+            .filter(|m| !m.name().is_empty())
+            .map(|m| {
+                let last = m.last_frame.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(|f| f.time);
+                format!(
+                    "{{\"id\": \"{}\", \"mode\": \"{}\", \"frames\": {}, \"timestamp\": {}}}",
+                    json_escape(&m.name()),
+                    m.mode.get_mode_name(),
+                    m.stats.frames.load(Ordering::Relaxed),
+                    last.map_or("null".to_string(), |t| format!("\"{}\"", format_timestamp(t)))
+                )
+            })
+            .collect();
+        Response::json(200, format!("[{}]\n", meters.join(", ")))
+    }
+
+    fn last_frame(&self, meter: &Meter) -> Response {
+        match meter.last_frame.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(frame) => Response::json(200, json::line(frame) + "\n"),
+            None => error(404, "no frame received yet"),
+        }
+    }
+
+    /// {"meter": ..., "label": ..., "value": <typed>, "raw": ..., "date": <horodate>, "unit": ..., "timestamp": <reception>},
+    /// raw and date only for TIC labels, date and unit only if any
+    fn label(&self, meter: &Meter, label: &str) -> Response {
+        let Some(frame) = meter.last_frame.lock().unwrap_or_else(|e| e.into_inner()).clone() else {
+            return error(404, "no frame received yet");
+        };
+        let mut body = format!("{{\"meter\": \"{}\", \"label\": \"{}\"", json_escape(&frame.meter), json_escape(label));
+        if let Some((_, value)) = frame.values.iter().find(|(l, _)| l == label) {
+            body.push_str(&format!(", \"value\": {}", value.to_json()));
+            let (raw, date, unit) = meter.mode.with(|mode| {
+                let lv = mode.label_values().get(label).cloned();
+                (lv.as_ref().map(|lv| lv.value.clone()), lv.and_then(|lv| lv.timestamp), mode.get_ha_unit(label))
+            });
+            body.push_str(&format!(", \"raw\": \"{}\"", json_escape(&raw.unwrap_or_default())));
+            if let Some(date) = date {
+                body.push_str(&format!(", \"date\": \"{}\"", json_escape(&date)));
+            }
+            if let Some(unit) = unit {
+                body.push_str(&format!(", \"unit\": \"{}\"", unit));
+            }
+        } else if let Some(metric) = frame.metrics.iter().find(|m| m.id == label) {
+            body.push_str(&format!(", \"value\": {}", metric.value.to_json()));
+            if let Some(unit) = metric_info(label).and_then(|info| info.unit) {
+                body.push_str(&format!(", \"unit\": \"{}\"", unit));
+            }
+        } else {
+            return error(404, &format!("label {} not in the last frame", label));
+        }
+        body.push_str(&format!(", \"timestamp\": \"{}\"}}\n", format_timestamp(frame.time)));
+        Response::json(200, body)
+    }
+
+    /// {"meter": ..., "label": ..., "minutes": [{"timestamp": <start>, "min": ..., "max": ..., "avg": ..., "last": ..., "samples": ...}, ...]},
+    /// from the last day by default
+    fn history(&self, meter: &Meter, request: &Request) -> Response {
+        let Some(path) = &self.history else { return error(404, "no history, the sqlite output is not enabled") };
+        let Some(label) = request.param("label").filter(|l| !l.is_empty()) else { return error(400, "missing label") };
+        let mut times = Vec::new();
+        for name in ["from", "to"] {
+            match request.param(name).map(|value| parse_timestamp(&value).ok_or(value)) {
+                Some(Ok(time)) => times.push(Some(time)),
+                Some(Err(value)) => return error(400, &format!("invalid {} {}, expected e.g. 2025-01-15T12:00:00Z", name, value)),
+                None => times.push(None),
+            }
+        }
+        let to = times[1].unwrap_or_else(SystemTime::now);
+        let from = times[0].unwrap_or_else(|| to.checked_sub(HISTORY_PERIOD).unwrap_or(UNIX_EPOCH));
+        let millis = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64);
+        let (from, to) = (millis(from), millis(to));
+        let rows = match sqlite::history(path, &meter.name(), &label, from, to) {
+            Ok(rows) => rows,
+            Err(e) => return error(503, &format!("history unavailable: {}", e)),
+        };
+        let minutes: Vec<String> = rows.iter()
+            .map(|r| format!(
+                "{{\"timestamp\": \"{}\", \"min\": {}, \"max\": {}, \"avg\": {}, \"last\": {}, \"samples\": {}}}",
+                format_timestamp(UNIX_EPOCH + Duration::from_millis(r.time.max(0) as u64)), r.min, r.max, r.avg, r.last, r.samples
+            ))
+            .collect();
+        Response::json(200, format!(
+            "{{\"meter\": \"{}\", \"label\": \"{}\", \"minutes\": [{}]}}\n",
+            json_escape(&meter.name()), json_escape(&label), minutes.join(", ")
+        ))
+    }
+
+    /// {"status": "ok" or "unhealthy", "meters": [{"id": ..., "input": "open" or "closed", "last_frame_age": <s>, "healthy": ...}],
+    /// "mqtt": {"connected": ...} or null without MQTT output, "outputs": [{"name": ..., "queued": ..., "dropped": ..., "healthy": ...}]}
+    fn health(&self) -> Response {
+        let mut healthy = true;
+        let meters: Vec<String> = self.meters.iter()
+            .map(|m| {
+                let open = m.stats.input_open.load(Ordering::Relaxed);
+                let last = m.last_frame.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(|f| f.time);
+                let age = match last {
+                    Some(time) => SystemTime::now().duration_since(time).unwrap_or_default(),
+                    None => self.started.elapsed(),
+                };
+                let ok = open && age <= METER_TIMEOUT;
+                healthy &= ok;
+                format!(
+                    "{{\"id\": \"{}\", \"input\": \"{}\", \"last_frame_age\": {}, \"healthy\": {}}}",
+                    json_escape(&m.name()),
+                    if open { "open" } else { "closed" },
+                    last.map_or("null".to_string(), |_| format!("{:.1}", age.as_secs_f64())),
+                    ok
+                )
+            })
+            .collect();
+        let mqtt = if let Some(mqtt) = &self.mqtt {
+            let connected = mqtt.connected.load(Ordering::Relaxed);
+            healthy &= connected;
+            format!("{{\"connected\": {}}}", connected)
+        } else {
+            "null".to_string()
+        };
+        let outputs: Vec<String> = self.outputs.iter()
+            .map(|o| {
+                let queued = o.queued.load(Ordering::Relaxed);
+                let ok = queued < QUEUE_SIZE;
+                healthy &= ok;
+                format!(
+                    "{{\"name\": \"{}\", \"queued\": {}, \"dropped\": {}, \"healthy\": {}}}",
+                    o.name, queued, o.dropped.load(Ordering::Relaxed), ok
+                )
+            })
+            .collect();
+        Response::json(
+            if healthy { 200 } else { 503 },
+            format!(
+                "{{\"status\": \"{}\", \"meters\": [{}], \"mqtt\": {}, \"outputs\": [{}]}}\n",
+                if healthy { "ok" } else { "unhealthy" }, meters.join(", "), mqtt, outputs.join(", ")
+            ),
+        )
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_end_matches('/').split('/').skip(1).collect();
+        match segments.as_slice() {
+            ["meters"] => self.meters(),
+            ["meters", id, rest @ ..] => {
+                let Some(meter) = self.meter(id) else { return error(404, &format!("unknown meter {}", id)) };
+                match rest {
+                    [] => self.last_frame(meter),
+                    ["labels", label] => self.label(meter, label),
+                    ["history"] => self.history(meter, request),
+                    _ => error(404, "not found"),
+                }
+            }
+            ["health"] => self.health(),
+            ["metrics"] => prometheus::response(&self.meters, self.mqtt.as_deref(), &self.outputs),
+            ["stream"] => stream::response(request, &self.meters, &self.subscribers),
+            _ => error(404, "not found"),
+        }
+    }
+}
+
+/// Serve the API on `addr`
+pub fn spawn(addr: &str, api: Api) -> std::io::Result<()> {
+    http::spawn(addr, move |request: &Request| api.handle(request))
+}
+
+/// GET /health from the API listening on `listen`, for `--healthcheck`: the body, an error if unhealthy or unreachable
+pub fn check(listen: &str) -> Result<String, String> {
+    // A wildcard address is reached on the loopback
+    let addr = listen.replacen("0.0.0.0:", "127.0.0.1:", 1).replacen("[::]:", "[::1]:", 1);
+    let timeout = Duration::from_secs(5);
+    let request = || -> std::io::Result<String> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| std::io::Error::other("no address"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        write!(stream, "GET /health HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", addr)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+    let response = request().map_err(|e| format!("{}: {}", addr, e))?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    match head.split_whitespace().nth(1) {
+        Some("200") => Ok(body.to_string()),
+        _ => Err(body.to_string()),
+    }
+}
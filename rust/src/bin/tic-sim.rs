@@ -0,0 +1,502 @@
+//! TIC meter simulator: generates historique or standard frames with valid checksums,
+//! evolving indexes and tariff periods, to exercise tic2mqtt without a meter.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tic2mqtt::tic::frame::{ETX, Group, STX, format_group};
+use tic2mqtt::tic::historique::HistoriqueTIC;
+use tic2mqtt::tic::standard::StandardTIC;
+use tic2mqtt::utils::{civil_from_days, parse_timestamp};
+
+const USAGE: &str = "\
+USAGE:
+    tic-sim [OPTIONS]
+
+OPTIONS:
+    --mode <MODE>              TIC mode (standard/historique), default historique
+    --tariff <OPTION>          Tariff option (base/hc/ejp/tempo), default base
+    --phases <N>               1 or 3 phases, default 1
+    --address <ADDRESS>        Meter address (ADCO/ADSC), 12 digits
+    --power <VA>               Mean apparent power, default 1500
+    --output <PATH>            Write to a file, '-' for stdout (default)
+    --pty <LINK>               Write to a new pseudo-terminal, linked from LINK
+    --baudrate <BAUD>          Serial speed, default from the TIC mode
+    --speed <FACTOR>           Output pace, 1 as sent at the serial speed (default), 0 no pacing
+    --time-factor <FACTOR>     Simulated time per real time, default 1
+    --start <TIME>             Simulated start time, e.g. 2025-01-01T12:00:00Z, default now
+    --frames <N>               Stop after N frames, default never
+    --seed <N>                 Random seed, default 1
+    -h, --help                 Display this help message";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tariff {
+    Base,
+    Hc,
+    Ejp,
+    Tempo,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Colour {
+    Blue,
+    White,
+    Red,
+}
+
+struct Options {
+    standard: bool,
+    tariff: Tariff,
+    phases: usize,
+    address: Option<String>,
+    power: f64,
+    output: Option<String>,
+    pty: Option<String>,
+    baudrate: Option<u32>,
+    speed: f64,
+    time_factor: f64,
+    start: Option<SystemTime>,
+    frames: Option<u64>,
+    seed: u64,
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut opt = Options {
+        standard: false, tariff: Tariff::Base, phases: 1, address: None, power: 1500.0, output: None, pty: None,
+        baudrate: None, speed: 1.0, time_factor: 1.0, start: None, frames: None, seed: 1,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        if name == "-h" || name == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        let value = match inline {
+            Some(value) => value,
+            None => args.next().ok_or_else(|| format!("option '{}' requires a value", name))?,
+        };
+        let invalid = |expected: &str| format!("invalid value '{}' for {}: expected {}", value, name, expected);
+        let factor = || value.parse().ok().filter(|f: &f64| f.is_finite() && *f >= 0.0);
+        match name.as_str() {
+            "--mode" => opt.standard = match value.as_str() {
+                "standard" => true,
+                "historique" => false,
+                _ => return Err(invalid("standard or historique")),
+            },
+            "--tariff" => opt.tariff = match value.as_str() {
+                "base" => Tariff::Base,
+                "hc" => Tariff::Hc,
+                "ejp" => Tariff::Ejp,
+                "tempo" => Tariff::Tempo,
+                _ => return Err(invalid("base, hc, ejp or tempo")),
+            },
+            "--phases" => opt.phases = match value.as_str() {
+                "1" => 1,
+                "3" => 3,
+                _ => return Err(invalid("1 or 3")),
+            },
+            "--address" => {
+                if value.len() != 12 || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid("12 digits"));
+                }
+                opt.address = Some(value);
+            }
+            "--power" => opt.power = value.parse().ok().filter(|p: &f64| p.is_finite() && *p > 0.0).ok_or_else(|| invalid("a power in VA"))?,
+            "--output" => opt.output = Some(value),
+            "--pty" => opt.pty = Some(value),
+            "--baudrate" => opt.baudrate = Some(value.parse().ok().filter(|b| *b > 0).ok_or_else(|| invalid("a positive serial speed"))?),
+            "--speed" => opt.speed = factor().ok_or_else(|| invalid("a pace factor"))?,
+            "--time-factor" => opt.time_factor = factor().ok_or_else(|| invalid("a time factor"))?,
+            "--start" => opt.start = Some(parse_timestamp(&value).ok_or_else(|| invalid("a UTC time, e.g. 2025-01-01T12:00:00Z"))?),
+            "--frames" => opt.frames = Some(value.parse().map_err(|_| invalid("a number of frames"))?),
+            "--seed" => opt.seed = value.parse().map_err(|_| invalid("a number"))?,
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+    if opt.output.is_some() && opt.pty.is_some() {
+        return Err("--output and --pty are mutually exclusive".to_string());
+    }
+    Ok(opt)
+}
+
+/// xorshift64* pseudo-random generator, reproducible with a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Stable pseudo-random number in 0..100 of a day, so tomorrow's colour is known today
+fn day_hash(day: i64) -> u64 {
+    let mut h = (day as u64).wrapping_mul(0x9E3779B97F4A7C15);
+    h ^= h >> 29;
+    (h.wrapping_mul(0xBF58476D1CE4E5B9) >> 32) % 100
+}
+
+/// Local time of the meter in seconds: UTC+2 in summer ('E', April to October), UTC+1 in winter ('H')
+fn local_time(utc: f64) -> (f64, char) {
+    let (_, month, _) = civil_from_days((utc / 86400.0).floor() as i64);
+    if (4..=10).contains(&month) { (utc + 7200.0, 'E') } else { (utc + 3600.0, 'H') }
+}
+
+fn is_winter(local_day: i64) -> bool {
+    let (_, month, _) = civil_from_days(local_day);
+    !(4..=10).contains(&month)
+}
+
+/// Tempo colour of a Tempo day, which starts at 6:00
+fn tempo_colour(day: i64) -> Colour {
+    match day_hash(day) {
+        h if is_winter(day) && h < 12 => Colour::Red,
+        h if h < 15 => Colour::White,
+        _ => Colour::Blue,
+    }
+}
+
+fn is_ejp_day(day: i64) -> bool {
+    is_winter(day) && day_hash(day) < 15
+}
+
+struct Meter {
+    standard: bool,
+    tariff: Tariff,
+    phases: usize,
+    address: String,
+    /// Subscribed power in kVA
+    subscribed: u32,
+    mean_power: f64,
+    /// Energy indexes in Wh, by tariff period
+    indexes: [f64; 6],
+    /// Apparent power by phase in VA
+    power: Vec<f64>,
+    /// Maximum power of the day, and its time
+    max_power: (f64, f64),
+    /// Simulated time, seconds since the epoch (UTC)
+    time: f64,
+    rng: Rng,
+}
+
+impl Meter {
+    fn new(opt: &Options) -> Self {
+        let mut rng = Rng(opt.seed.max(1));
+        let subscribed = if opt.phases == 3 { 12 } else { 6 };
+        let time = opt.start.unwrap_or_else(SystemTime::now).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let mut indexes = [0.0; 6];
+        for index in indexes.iter_mut() {
+            *index = (rng.next_f64() * 5e6).floor() + 1e6;
+        }
+        let address = opt.address.clone().unwrap_or_else(|| if opt.standard { "021728123456" } else { "031762001234" }.to_string());
+        let mean_power = opt.power / opt.phases as f64;
+        Meter {
+            standard: opt.standard, tariff: opt.tariff, phases: opt.phases, address, subscribed, mean_power,
+            indexes, power: vec![mean_power; opt.phases], max_power: (opt.power, time), time, rng,
+        }
+    }
+
+    /// Current tariff period, index in `indexes`: 0 the off-peak/normal period, 1 the peak period, Tempo blue, white, red
+    fn period(&self) -> usize {
+        let (local, _) = local_time(self.time);
+        let day = (local / 86400.0).floor() as i64;
+        let hour = (local.rem_euclid(86400.0) / 3600.0) as u32;
+        let off_peak = !(6..22).contains(&hour);
+        match self.tariff {
+            Tariff::Base => 0,
+            Tariff::Hc => if off_peak { 0 } else { 1 },
+            Tariff::Ejp => if is_ejp_day(day) && hour >= 7 { 1 } else { 0 },
+            Tariff::Tempo => {
+                let colour = tempo_colour(((local - 6.0 * 3600.0) / 86400.0).floor() as i64);
+                colour as usize * 2 + if off_peak { 0 } else { 1 }
+            }
+        }
+    }
+
+    /// Tempo colour of today and tomorrow
+    fn tempo_colours(&self) -> (Colour, Colour) {
+        let (local, _) = local_time(self.time);
+        let day = ((local - 6.0 * 3600.0) / 86400.0).floor() as i64;
+        (tempo_colour(day), tempo_colour(day + 1))
+    }
+
+    /// Advance the simulated time by `dt` seconds
+    fn step(&mut self, dt: f64) {
+        let max = self.subscribed as f64 * 1000.0 / self.phases as f64;
+        for p in self.power.iter_mut() {
+            // Random walk around the mean load, with occasional appliance spikes
+            let spike = if self.rng.next_f64() < 0.02 { self.mean_power * 2.0 } else { 0.0 };
+            *p += (self.mean_power - *p) * 0.2 + (self.rng.next_f64() - 0.5) * self.mean_power * 0.3 + spike;
+            *p = p.clamp(50.0, max);
+        }
+        let total: f64 = self.power.iter().sum();
+        let period = self.period();
+        self.indexes[period] += total * dt / 3600.0;
+        let day = |t: f64| (local_time(t).0 / 86400.0).floor() as i64;
+        if day(self.time + dt) != day(self.max_power.1) || total > self.max_power.0 {
+            self.max_power = (total, self.time + dt);
+        }
+        self.time += dt;
+    }
+
+    fn horodate(time: f64) -> String {
+        let (local, season) = local_time(time);
+        let secs = local.floor() as i64;
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let tod = secs.rem_euclid(86400);
+        format!("{}{:02}{:02}{:02}{:02}{:02}{:02}", season, year % 100, month, day, tod / 3600, tod / 60 % 60, tod % 60)
+    }
+
+    fn groups(&self) -> Vec<Group> {
+        if self.standard { self.standard_groups() } else { self.historique_groups() }
+    }
+
+    fn historique_groups(&self) -> Vec<Group> {
+        let mut groups = Vec::new();
+        let mut push = |label: &str, value: String| groups.push(Group { label: label.to_string(), value, horodate: None });
+        let index = |i: usize| format!("{:09}", self.indexes[i] as u64);
+        let period = self.period();
+        push("ADCO", self.address.clone());
+        let (optarif, index_labels, ptec): (&str, &[&str], &[&str]) = match self.tariff {
+            Tariff::Base => ("BASE", &["BASE"], &["TH.."]),
+            Tariff::Hc => ("HC..", &["HCHC", "HCHP"], &["HC..", "HP.."]),
+            Tariff::Ejp => ("EJP.", &["EJPHN", "EJPHPM"], &["HN..", "PM.."]),
+            Tariff::Tempo => ("BBR(", &["BBRHCJB", "BBRHPJB", "BBRHCJW", "BBRHPJW", "BBRHCJR", "BBRHPJR"], &["HCJB", "HPJB", "HCJW", "HPJW", "HCJR", "HPJR"]),
+        };
+        push("OPTARIF", optarif.to_string());
+        push("ISOUSC", format!("{}", self.subscribed * 5 / self.phases as u32));
+        for (i, label) in index_labels.iter().enumerate() {
+            push(label, index(i));
+        }
+        push("PTEC", ptec[period].to_string());
+        if self.tariff == Tariff::Tempo {
+            let demain = match self.tempo_colours().1 {
+                Colour::Blue => "BLEU",
+                Colour::White => "BLAN",
+                Colour::Red => "ROUG",
+            };
+            push("DEMAIN", demain.to_string());
+        }
+        let total: f64 = self.power.iter().sum();
+        if self.phases == 1 {
+            push("IINST", format!("{:03}", (total / 230.0).round() as u32));
+            push("IMAX", "090".to_string());
+            push("PAPP", format!("{:05}", total as u32));
+        } else {
+            for (i, p) in self.power.iter().enumerate() {
+                push(&format!("IINST{}", i + 1), format!("{:03}", (p / 230.0).round() as u32));
+            }
+            for i in 1..=3 {
+                push(&format!("IMAX{}", i), "060".to_string());
+            }
+            push("PMAX", format!("{:05}", self.max_power.0 as u32));
+            push("PAPP", format!("{:05}", total as u32));
+        }
+        push("HHPHC", "A".to_string());
+        push("MOTDETAT", "000000".to_string());
+        if self.phases == 3 {
+            push("PPOT", "00".to_string());
+        }
+        groups
+    }
+
+    fn standard_groups(&self) -> Vec<Group> {
+        let mut groups = Vec::new();
+        let mut push = |label: &str, value: String, horodate: Option<String>| groups.push(Group { label: label.to_string(), value, horodate });
+        let now = Self::horodate(self.time);
+        let period = self.period();
+        let (ngtf, ltarf): (&str, &[&str]) = match self.tariff {
+            Tariff::Base => ("BASE", &["BASE"]),
+            Tariff::Hc => ("H PLEINE/CREUSE", &["HEURE CREUSE", "HEURE PLEINE"]),
+            Tariff::Ejp => ("EJP", &["HEURE NORMALE", "HEURE POINTE"]),
+            Tariff::Tempo => ("TEMPO", &["HC BLEU", "HP BLEU", "HC BLANC", "HP BLANC", "HC ROUGE", "HP ROUGE"]),
+        };
+        let used = ltarf.len();
+        push("ADSC", self.address.clone(), None);
+        push("VTIC", "02".to_string(), None);
+        push("DATE", String::new(), Some(now.clone()));
+        push("NGTF", format!("{:^16}", ngtf), None);
+        push("LTARF", format!("{:^16}", ltarf[period]), None);
+        push("EAST", format!("{:09}", self.indexes[..used].iter().sum::<f64>() as u64), None);
+        for i in 0..10 {
+            let value = if i < used { self.indexes[i] as u64 } else { 0 };
+            push(&format!("EASF{:02}", i + 1), format!("{:09}", value), None);
+        }
+        // Distributor indexes: off-peak and peak periods
+        let off_peak: f64 = self.indexes[..used].iter().step_by(2).sum();
+        let peak: f64 = self.indexes[..used].iter().skip(1).step_by(2).sum();
+        for (i, value) in [off_peak, peak, 0.0, 0.0].iter().enumerate() {
+            push(&format!("EASD{:02}", i + 1), format!("{:09}", *value as u64), None);
+        }
+        let voltages: Vec<u32> = (0..self.phases).map(|i| 229 + (self.time as u64 / 60 + i as u64) as u32 % 4).collect();
+        for (i, p) in self.power.iter().enumerate() {
+            push(&format!("IRMS{}", i + 1), format!("{:03}", (p / voltages[i] as f64).round() as u32), None);
+        }
+        for (i, u) in voltages.iter().enumerate() {
+            push(&format!("URMS{}", i + 1), format!("{:03}", u), None);
+        }
+        push("PREF", format!("{:02}", self.subscribed), None);
+        push("PCOUP", format!("{:02}", self.subscribed), None);
+        let total: f64 = self.power.iter().sum();
+        push("SINSTS", format!("{:05}", total as u32), None);
+        if self.phases == 3 {
+            for (i, p) in self.power.iter().enumerate() {
+                push(&format!("SINSTS{}", i + 1), format!("{:05}", *p as u32), None);
+            }
+        }
+        push("SMAXSN", format!("{:05}", self.max_power.0 as u32), Some(Self::horodate(self.max_power.1)));
+        for (i, u) in voltages.iter().enumerate() {
+            push(&format!("UMOY{}", i + 1), format!("{:03}", u), Some(now.clone()));
+        }
+        // Tempo colours of today and tomorrow in bits 24-27
+        let stge = match self.tariff {
+            Tariff::Tempo => {
+                let (today, tomorrow) = self.tempo_colours();
+                ((today as u32 + 1) << 24) | ((tomorrow as u32 + 1) << 26)
+            }
+            _ => 0,
+        };
+        push("STGE", format!("{:08X}", stge), None);
+        push("MSG1", format!("{:<32}", "PAS DE          MESSAGE"), None);
+        push("PRM", format!("{:014}", 21_000_000_000_000u64 + self.address[6..].parse::<u64>().unwrap_or(0)), None);
+        push("RELAIS", "000".to_string(), None);
+        push("NTARF", format!("{:02}", period + 1), None);
+        push("NJOURF", "00".to_string(), None);
+        push("NJOURF+1", "00".to_string(), None);
+        groups
+    }
+}
+
+/// Create a pseudo-terminal, returns its master side and the path of its slave side
+#[cfg(target_os = "linux")]
+fn open_pty() -> io::Result<(File, File, String)> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: plain libc calls on a descriptor we own, ptsname_r writes at most buf.len() bytes
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = [0 as libc::c_char; 128];
+        if libc::ptsname_r(fd, buf.as_mut_ptr(), buf.len()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let path = std::ffi::CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned();
+        // Keep the slave side open in raw mode: no echo, and no hang up while the reader reopens it
+        let slave = std::fs::OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut termios: libc::termios = std::mem::zeroed();
+        let slave_fd = std::os::fd::AsRawFd::as_raw_fd(&slave);
+        if libc::tcgetattr(slave_fd, &mut termios) == 0 {
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(slave_fd, libc::TCSANOW, &termios);
+        }
+        Ok((master, slave, path))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_pty() -> io::Result<(File, File, String)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals are only supported on Linux"))
+}
+
+fn run(opt: Options, mut out: Box<dyn Write>, shutdown: &AtomicBool) -> io::Result<()> {
+    let mut meter = Meter::new(&opt);
+    let baudrate = opt.baudrate.unwrap_or(if opt.standard { StandardTIC::BAUDRATE } else { HistoriqueTIC::BAUDRATE });
+    // Transmission time of a byte, 10 bits with 7E1 framing
+    let byte_time = 10.0 / baudrate as f64;
+    let start = Instant::now();
+    let mut elapsed = 0.0;
+    let mut frames = 0;
+    while !shutdown.load(Ordering::SeqCst) && opt.frames.is_none_or(|n| frames < n) {
+        let mut frame_bytes = 0;
+        let mut lines = vec![format!("{}", STX)];
+        lines.extend(meter.groups().iter().map(|g| format!("\n{}\r", format_group(g, meter.standard))));
+        lines.push(format!("{}", ETX));
+        for line in lines {
+            out.write_all(line.as_bytes())?;
+            out.flush()?;
+            frame_bytes += line.len();
+            if opt.speed > 0.0 {
+                elapsed += line.len() as f64 * byte_time / opt.speed;
+                let deadline = start + Duration::from_secs_f64(elapsed);
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            }
+        }
+        meter.step(frame_bytes as f64 * byte_time * opt.time_factor);
+        frames += 1;
+    }
+    Ok(())
+}
+
+fn main() {
+    let opt = match parse_args(std::env::args().skip(1)) {
+        Ok(opt) => opt,
+        Err(e) => {
+            eprintln!("error: {}\n\nTry 'tic-sim --help' for more information.", e);
+            std::process::exit(2);
+        }
+    };
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_ctrlc = shutdown.clone();
+    ctrlc::set_handler(move || shutdown_ctrlc.store(true, Ordering::SeqCst)).expect("Error setting Ctrl-C handler");
+
+    let mut _slave = None;
+    let out: Box<dyn Write> = match (&opt.output, &opt.pty) {
+        (Some(path), None) if path != "-" => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("error: cannot create {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        (None, Some(link)) => match open_pty() {
+            Ok((master, slave, path)) => {
+                // Replace a stale link from a previous run
+                if std::fs::symlink_metadata(link).is_ok_and(|m| m.file_type().is_symlink()) {
+                    let _ = std::fs::remove_file(link);
+                }
+                #[cfg(unix)]
+                if let Err(e) = std::os::unix::fs::symlink(&path, link) {
+                    eprintln!("error: cannot create link {}: {}", link, e);
+                    std::process::exit(1);
+                }
+                eprintln!("tic-sim: writing to {} ({})", link, path);
+                _slave = Some(slave);
+                Box::new(master)
+            }
+            Err(e) => {
+                eprintln!("error: cannot create pseudo-terminal: {}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => Box::new(io::stdout().lock()),
+    };
+
+    let link = opt.pty.clone();
+    let result = run(opt, out, &shutdown);
+    if let Some(link) = link {
+        let _ = std::fs::remove_file(link);
+    }
+    match result {
+        Ok(()) => {}
+        // The reader went away, e.g. piped to head
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
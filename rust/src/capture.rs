@@ -0,0 +1,117 @@
+//! Raw captures of the serial line, as written by `--record` and read by `--replay`.
+//!
+//! A capture is the bytes received, unchanged, with timestamp lines `#T <ISO 8601 UTC>`
+//! inserted between the lines of the meter, at the start of each frame and at least
+//! every second. A plain capture without timestamp lines is valid too.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+use tic2mqtt::tic::frame::ETX;
+use tic2mqtt::utils::{format_timestamp, parse_timestamp};
+
+use crate::output;
+
+/// Start of a timestamp line
+pub const MARKER: &str = "#T ";
+/// Size of the capture file before it is rotated
+pub const RECORD_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Rotated capture files kept, as <path>.1 (newest) to <path>.<RECORD_KEEP>
+pub const RECORD_KEEP: usize = 5;
+/// Longest time between two timestamp lines while data is received
+const MARK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time of a timestamp line, None for other lines
+pub fn parse_marker(line: &[u8]) -> Option<SystemTime> {
+    let line = std::str::from_utf8(line.strip_prefix(MARKER.as_bytes())?).ok()?;
+    parse_timestamp(line.trim_end_matches(['\r', '\n']))
+}
+
+/// Writer of a rotating capture file
+pub struct Recorder {
+    path: String,
+    file: Option<File>,
+    size: u64,
+    /// The last byte written ends a line, a timestamp line can be inserted
+    at_line_start: bool,
+    /// A frame ended since the last timestamp line
+    frame_end: bool,
+    last_mark: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn new(path: String) -> Self {
+        Recorder { path, file: None, size: 0, at_line_start: true, frame_end: false, last_mark: None }
+    }
+
+    /// Record bytes received, recording stops on error
+    pub fn write(&mut self, data: &[u8]) {
+        if let Err(e) = self.try_write(data) {
+            log::error!("[Serial] {}: record error: {}, recording stopped", self.path, e);
+            self.path.clear();
+            self.file = None;
+        }
+    }
+
+    fn try_write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        if self.path.is_empty() { return Ok(()); }
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        while !data.is_empty() {
+            if self.at_line_start && (self.frame_end || self.last_mark.is_none_or(|t| t.elapsed() >= MARK_INTERVAL)) {
+                if self.size >= RECORD_MAX_SIZE {
+                    self.rotate()?;
+                }
+                self.write_raw(format!("{}{}\n", MARKER, format_timestamp(SystemTime::now())).as_bytes())?;
+                self.frame_end = false;
+                self.last_mark = Some(Instant::now());
+            }
+            // Up to the end of the line
+            let end = data.iter().position(|&b| b == b'\n').map_or(data.len(), |i| i + 1);
+            let (line, rest) = data.split_at(end);
+            self.write_raw(line)?;
+            self.frame_end |= line.contains(&(ETX as u8));
+            self.at_line_start = line.ends_with(b"\n");
+            data = rest;
+        }
+        Ok(())
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.write_all(data)?;
+            self.size += data.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Shift <path>.N to <path>.N+1 and start a new <path>
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        output::rotate(&self.path, RECORD_KEEP)?;
+        self.file = Some(File::create(&self.path)?);
+        self.size = 0;
+        log::info!("[Serial] {}: capture file rotated", self.path);
+        Ok(())
+    }
+}
+
+/// Reader recording the bytes read
+pub struct Tee<'a, R> {
+    pub inner: R,
+    pub recorder: Option<&'a mut Recorder>,
+}
+
+impl<R: Read> Read for Tee<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
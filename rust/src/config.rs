@@ -0,0 +1,971 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use tic2mqtt::derived::{Tariff, metric_info};
+use tic2mqtt::tic::{TicModeEnum, TicModeHandle};
+
+use crate::csv::CsvConfig;
+use crate::discovery::LabelOverride;
+use crate::influx::{InfluxConfig, InfluxTarget};
+use crate::output::OutputKind;
+use crate::sqlite::SqliteConfig;
+use crate::mqtt::{DiscoveryPolicy, MqttConfig, TlsConfig};
+use crate::topics::{MQTT_DISCOVERY_PREFIX, MQTT_ID_BASE, Topics};
+
+pub const USAGE: &str = "\
+USAGE:
+    tic2mqtt [OPTIONS]
+
+OPTIONS:
+    --config <PATH>                TOML configuration file [env: CONFIG_FILE]
+    --serial <PATH>                Serial device path, '-' reads stdin [env: SERIAL_PORT]
+    --mode <MODE>                  TIC mode (standard/historique) [env: TIC_MODE]
+    --baudrate <BAUD>              Serial speed, default from the TIC mode [env: SERIAL_BAUDRATE]
+    --meter-id <NAME>              Meter name in topics, default the meter address [env: METER_ID]
+    --replay <PATH>                Read a capture file instead of the serial port [env: REPLAY_FILE]
+    --replay-speed <FACTOR>        Replay pace, 1 the original, 0 no pacing (default) [env: REPLAY_SPEED]
+    --record <PATH>                Record the raw serial data to a capture file [env: RECORD_FILE]
+    --power-window <SECONDS>       Active power estimate window, 0 disables it, default 60 [env: POWER_WINDOW]
+    --state-file <PATH>            File keeping the consumption counters across restarts [env: STATE_FILE]
+    --production-topic <TOPIC>     MQTT topic of the production index in Wh, for the self-consumption [env: PRODUCTION_TOPIC]
+    --metrics-listen <ADDR>        Serve Prometheus metrics on http://ADDR/metrics, e.g. 0.0.0.0:9100 [env: METRICS_LISTEN]
+    --http-listen <ADDR>           Serve the REST API and the metrics on http://ADDR/, e.g. 0.0.0.0:8080 [env: HTTP_LISTEN]
+    --outputs <LIST>               Outputs enabled side by side, among mqtt, influx, json, csv and sqlite, default
+                                   mqtt, and the others configured by their options below [env: OUTPUTS]
+    --json-output <PATH>           Write the frames as JSON lines to a file, '-' for stdout [env: JSON_OUTPUT]
+    --csv-dir <DIR>                Write the frames as CSV to a file per meter and day in DIR [env: CSV_DIR]
+    --csv-interval <SECONDS>       One CSV row per interval, 0 for every frame (default) [env: CSV_INTERVAL]
+    --sqlite-path <PATH>           Store the frames, and their values per minute, in a SQLite database [env: SQLITE_PATH]
+    --sqlite-retention <DAYS>      Days the frames are kept, 0 for ever, default 2 [env: SQLITE_RETENTION]
+    --sqlite-minute-retention <DAYS>
+                                   Days the values per minute are kept, 0 for ever, default 365 [env: SQLITE_MINUTE_RETENTION]
+    --influx-url <URL>             Write the frames as InfluxDB line protocol to udp://HOST:PORT,
+                                   http://HOST:PORT/PATH?QUERY or file://PATH [env: INFLUX_URL]
+    --influx-token <TOKEN>         InfluxDB API token [env: INFLUX_TOKEN]
+    --influx-token-file <PATH>     File containing the InfluxDB API token [env: INFLUX_TOKEN_FILE]
+    --mqtt-server <URL>            MQTT broker address [env: MQTT_SERVER]
+    --mqtt-user <USERNAME>         MQTT username [env: MQTT_USER]
+    --mqtt-pass <PASSWORD>         MQTT password [env: MQTT_PASS]
+    --mqtt-pass-file <PATH>        File containing the MQTT password [env: MQTT_PASS_FILE]
+    --mqtt-client-id <ID>          MQTT client identifier [env: MQTT_CLIENT_ID]
+    --mqtt-qos <QOS>               QoS of published messages (0/1/2) [env: MQTT_QOS]
+    --mqtt-topic-prefix <TOPIC>    Prefix of the state topics [env: MQTT_TOPIC_PREFIX]
+    --mqtt-ca <PATH>               CA certificates to verify the broker (PEM) [env: MQTT_CA]
+    --mqtt-cert <PATH>             Client certificate (PEM) [env: MQTT_CERT]
+    --mqtt-key <PATH>              Client private key (PEM) [env: MQTT_KEY]
+    --mqtt-key-pass <PASSWORD>     Passphrase of the client private key [env: MQTT_KEY_PASS]
+    --mqtt-key-pass-file <PATH>    File containing the key passphrase [env: MQTT_KEY_PASS_FILE]
+    --discovery <POLICY>           Home Assistant discovery (all/seen/none) [env: DISCOVERY]
+    --discovery-prefix <TOPIC>     Home Assistant discovery prefix [env: DISCOVERY_PREFIX]
+    --log-level <LEVEL>            Log level (error/warn/info/debug/trace) [env: LOG_LEVEL]
+    --print-config                 Print the effective configuration and exit
+    --healthcheck                  Query /health on the --http-listen address, exit 1 if unhealthy or failing
+    -h, --help                     Display this help message
+    -V, --version                  Display version information
+
+Options may also be spelled with underscores (e.g. --mqtt_server).";
+
+/// Raw command line options, before merging with the environment
+#[derive(Default)]
+pub struct Opt {
+    pub config: Option<String>,
+    pub serial: Option<String>,
+    pub mode: Option<String>,
+    pub baudrate: Option<String>,
+    pub meter_id: Option<String>,
+    pub replay: Option<String>,
+    pub replay_speed: Option<String>,
+    pub record: Option<String>,
+    pub power_window: Option<String>,
+    pub state_file: Option<String>,
+    pub production_topic: Option<String>,
+    pub metrics_listen: Option<String>,
+    pub http_listen: Option<String>,
+    pub outputs: Option<String>,
+    pub json_output: Option<String>,
+    pub csv_dir: Option<String>,
+    pub csv_interval: Option<String>,
+    pub sqlite_path: Option<String>,
+    pub sqlite_retention: Option<String>,
+    pub sqlite_minute_retention: Option<String>,
+    pub influx_url: Option<String>,
+    pub influx_token: Option<String>,
+    pub influx_token_file: Option<String>,
+    pub mqtt_server: Option<String>,
+    pub mqtt_user: Option<String>,
+    pub mqtt_pass: Option<String>,
+    pub mqtt_pass_file: Option<String>,
+    pub mqtt_client_id: Option<String>,
+    pub mqtt_qos: Option<String>,
+    pub mqtt_topic_prefix: Option<String>,
+    pub mqtt_ca: Option<String>,
+    pub mqtt_cert: Option<String>,
+    pub mqtt_key: Option<String>,
+    pub mqtt_key_pass: Option<String>,
+    pub mqtt_key_pass_file: Option<String>,
+    pub discovery: Option<String>,
+    pub discovery_prefix: Option<String>,
+    pub log_level: Option<String>,
+    pub print_config: bool,
+    pub healthcheck: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+/// Invalid command line
+pub struct ArgsError {
+    /// The first error
+    pub message: String,
+    /// --healthcheck was among the arguments, whose failures all exit with 1
+    pub healthcheck: bool,
+}
+
+/// Parse command line arguments (without the program name), all of them even after an error
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Opt, ArgsError> {
+    let mut opt = Opt::default();
+    let mut error = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Accept both "--name value" and "--name=value", with '-' or '_' as separator
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name.replace('_', "-"), Some(value.to_string())),
+            _ if arg.starts_with("--") => (arg.replace('_', "-"), None),
+            _ => (arg.clone(), None),
+        };
+        let slot = match name.as_str() {
+            "-h" | "--help" => { opt.help = true; None },
+            "-V" | "--version" => { opt.version = true; None },
+            "--print-config" => { opt.print_config = true; None },
+            "--healthcheck" => { opt.healthcheck = true; None },
+            "--config" => Some(&mut opt.config),
+            "--serial" => Some(&mut opt.serial),
+            "--mode" => Some(&mut opt.mode),
+            "--baudrate" => Some(&mut opt.baudrate),
+            "--meter-id" => Some(&mut opt.meter_id),
+            "--replay" => Some(&mut opt.replay),
+            "--replay-speed" => Some(&mut opt.replay_speed),
+            "--record" => Some(&mut opt.record),
+            "--power-window" => Some(&mut opt.power_window),
+            "--state-file" => Some(&mut opt.state_file),
+            "--production-topic" => Some(&mut opt.production_topic),
+            "--metrics-listen" => Some(&mut opt.metrics_listen),
+            "--http-listen" => Some(&mut opt.http_listen),
+            "--outputs" => Some(&mut opt.outputs),
+            "--json-output" => Some(&mut opt.json_output),
+            "--csv-dir" => Some(&mut opt.csv_dir),
+            "--csv-interval" => Some(&mut opt.csv_interval),
+            "--sqlite-path" => Some(&mut opt.sqlite_path),
+            "--sqlite-retention" => Some(&mut opt.sqlite_retention),
+            "--sqlite-minute-retention" => Some(&mut opt.sqlite_minute_retention),
+            "--influx-url" => Some(&mut opt.influx_url),
+            "--influx-token" => Some(&mut opt.influx_token),
+            "--influx-token-file" => Some(&mut opt.influx_token_file),
+            "--mqtt-server" => Some(&mut opt.mqtt_server),
+            "--mqtt-user" => Some(&mut opt.mqtt_user),
+            "--mqtt-pass" => Some(&mut opt.mqtt_pass),
+            "--mqtt-pass-file" => Some(&mut opt.mqtt_pass_file),
+            "--mqtt-client-id" => Some(&mut opt.mqtt_client_id),
+            "--mqtt-qos" => Some(&mut opt.mqtt_qos),
+            "--mqtt-topic-prefix" => Some(&mut opt.mqtt_topic_prefix),
+            "--mqtt-ca" => Some(&mut opt.mqtt_ca),
+            "--mqtt-cert" => Some(&mut opt.mqtt_cert),
+            "--mqtt-key" => Some(&mut opt.mqtt_key),
+            "--mqtt-key-pass" => Some(&mut opt.mqtt_key_pass),
+            "--mqtt-key-pass-file" => Some(&mut opt.mqtt_key_pass_file),
+            "--discovery" => Some(&mut opt.discovery),
+            "--discovery-prefix" => Some(&mut opt.discovery_prefix),
+            "--log-level" => Some(&mut opt.log_level),
+            _ => {
+                error.get_or_insert(format!("unknown option '{}'", arg));
+                continue;
+            }
+        };
+        match (slot, inline) {
+            (Some(slot), Some(value)) => *slot = Some(value),
+            (Some(slot), None) => match args.next() {
+                Some(value) => *slot = Some(value),
+                None => { error.get_or_insert(format!("option '{}' requires a value", name)); }
+            },
+            (None, Some(_)) => { error.get_or_insert(format!("option '{}' does not take a value", name)); }
+            (None, None) => {},
+        }
+    }
+    match error {
+        Some(message) => Err(ArgsError { message, healthcheck: opt.healthcheck }),
+        None => Ok(opt),
+    }
+}
+
+/// Content of the TOML configuration file, every key is optional
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    log_level: Option<String>,
+    outputs: Option<Vec<String>>,
+    serial: FileSerial,
+    mqtt: FileMqtt,
+    discovery: FileDiscovery,
+    derived: FileDerived,
+    tariff: Option<FileTariff>,
+    metrics: FileMetrics,
+    http: FileHttp,
+    influx: FileInflux,
+    json: FileJson,
+    csv: FileCsv,
+    sqlite: FileSqlite,
+    labels: BTreeMap<String, FileLabel>,
+    meters: Vec<FileMeter>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileSerial {
+    port: Option<String>,
+    mode: Option<String>,
+    baudrate: Option<i64>,
+    id: Option<String>,
+    replay: Option<String>,
+    replay_speed: Option<f64>,
+    record: Option<String>,
+}
+
+/// One entry of [[meters]], replacing [serial] to read several meters
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileMeter {
+    port: Option<String>,
+    mode: Option<String>,
+    baudrate: Option<i64>,
+    id: Option<String>,
+    replay: Option<String>,
+    replay_speed: Option<f64>,
+    record: Option<String>,
+    tariff: Option<FileTariff>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileMqtt {
+    server: Option<String>,
+    client_id: Option<String>,
+    user: Option<String>,
+    pass: Option<String>,
+    pass_file: Option<String>,
+    qos: Option<i64>,
+    topic_prefix: Option<String>,
+    ca: Option<String>,
+    cert: Option<String>,
+    key: Option<String>,
+    key_pass: Option<String>,
+    key_pass_file: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileDiscovery {
+    policy: Option<String>,
+    prefix: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileDerived {
+    power_window: Option<f64>,
+    state_file: Option<String>,
+    production_topic: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileMetrics {
+    listen: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileHttp {
+    listen: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileInflux {
+    url: Option<String>,
+    token: Option<String>,
+    token_file: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileJson {
+    path: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileCsv {
+    dir: Option<String>,
+    interval: Option<f64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileSqlite {
+    path: Option<String>,
+    retention: Option<i64>,
+    minute_retention: Option<i64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileTariff {
+    subscription: f64,
+    prices: BTreeMap<String, f64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileLabel {
+    name: Option<String>,
+    device_class: Option<String>,
+    state_class: Option<String>,
+    unit: Option<String>,
+    enabled: Option<bool>,
+}
+
+impl FileConfig {
+    fn load(path: &str) -> Result<FileConfig, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("cannot read config file {}: {}", path, e))?;
+        toml::from_str(&content).map_err(|e| format!("config file {}: {}", path, e))
+    }
+}
+
+/// Where the frames of a meter are read from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    /// Serial device path
+    Serial(String),
+    Stdin,
+    /// Capture file, paced at `speed` times the original pace, as fast as possible if 0
+    Replay { path: String, speed: f64 },
+}
+
+/// Serial input and decoding settings of one meter
+pub struct MeterConfig {
+    /// Name used in topics, the meter address (ADCO/ADSC) if None
+    pub id: Option<String>,
+    pub input: Input,
+    /// Capture file of the raw serial data
+    pub record: Option<String>,
+    pub mode: TicModeEnum,
+    /// Serial speed, the TIC mode default if None
+    pub baudrate: Option<u32>,
+    /// Prices of the energy of this meter, the [tariff] ones if None
+    pub tariff: Option<Tariff>,
+}
+
+/// Effective configuration, merged from command line, environment, file and defaults
+pub struct Config {
+    /// Configuration file in use, if any
+    pub file: Option<String>,
+    pub meters: Vec<MeterConfig>,
+    /// Settings accepted but partly ignored, logged once the logger is set up
+    pub warnings: Vec<String>,
+    pub mqtt: MqttConfig,
+    pub log_level: log::LevelFilter,
+    /// Discovery overrides by label
+    pub labels: HashMap<String, LabelOverride>,
+    pub derived: DerivedConfig,
+    /// Address of the Prometheus metrics endpoint, none if None
+    pub metrics_listen: Option<String>,
+    /// Address of the REST API, none if None
+    pub http_listen: Option<String>,
+    /// Outputs enabled, in order
+    pub outputs: Vec<OutputKind>,
+    /// InfluxDB output settings, not configured if None
+    pub influx: Option<InfluxConfig>,
+    /// JSON lines file, "-" for stdout, not configured if None
+    pub json_output: Option<String>,
+    /// CSV output settings, not configured if None
+    pub csv: Option<CsvConfig>,
+    /// SQLite output settings, not configured if None
+    pub sqlite: Option<SqliteConfig>,
+}
+
+/// Settings of the metrics derived from the frames
+pub struct DerivedConfig {
+    /// Window of the active power estimate, disabled if zero
+    pub power_window: Duration,
+    /// File of the consumption counters, not saved if None
+    pub state_file: Option<String>,
+    /// MQTT topic of the production index, in Wh, no self-consumption if None
+    pub production_topic: Option<String>,
+    /// Prices of the energy, no costs if None
+    pub tariff: Option<Tariff>,
+}
+
+/// A setting value and where it came from, for error messages
+struct Setting {
+    value: String,
+    origin: String,
+    /// Precedence of the origin: 0 command line, 1 environment, 2 file, 3 default
+    rank: u8,
+}
+
+impl Setting {
+    fn invalid(&self, expected: &str) -> String {
+        format!("invalid value '{}' for {}: expected {}", self.value, self.origin, expected)
+    }
+
+    /// Read a secret from the file named by this setting, without trailing newlines
+    fn read_secret(&self) -> Result<String, String> {
+        let content = std::fs::read_to_string(&self.value)
+            .map_err(|e| format!("cannot read secret file '{}' from {}: {}", self.value, self.origin, e))?;
+        Ok(content.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn check_file(self) -> Result<String, String> {
+        if !std::path::Path::new(&self.value).is_file() {
+            return Err(format!("file '{}' from {} does not exist", self.value, self.origin));
+        }
+        Ok(self.value)
+    }
+}
+
+/// Pick a secret given directly or through a file, the most specific origin wins
+fn secret(value: Option<Setting>, file: Option<Setting>) -> Result<String, String> {
+    match (value, file) {
+        (Some(v), Some(f)) if v.rank == f.rank => Err(format!("{} and {} are mutually exclusive", v.origin, f.origin)),
+        (Some(v), Some(f)) if v.rank < f.rank => Ok(v.value),
+        (_, Some(f)) => f.read_secret(),
+        (Some(v), None) => Ok(v.value),
+        (None, None) => Ok(String::new()),
+    }
+}
+
+/// Look settings up by precedence: command line, environment, configuration file
+struct Resolver<'a> {
+    file: Option<&'a str>,
+}
+
+impl Resolver<'_> {
+    fn get(&self, cli: Option<String>, option: &str, var: &str, file: Option<String>, key: &str) -> Option<Setting> {
+        if let Some(value) = cli {
+            return Some(Setting { value, origin: format!("--{}", option), rank: 0 });
+        }
+        // An empty environment variable means unset
+        if let Some(value) = std::env::var(var).ok().filter(|v| !v.is_empty()) {
+            return Some(Setting { value, origin: var.to_string(), rank: 1 });
+        }
+        file.map(|value| Setting { value, origin: format!("{} in {}", key, self.file.unwrap_or("config file")), rank: 2 })
+    }
+
+    fn get_or(&self, cli: Option<String>, option: &str, var: &str, file: Option<String>, key: &str, default: &str) -> Setting {
+        self.get(cli, option, var, file, key)
+            .unwrap_or_else(|| Setting { value: default.to_string(), origin: format!("default {}", key), rank: 3 })
+    }
+}
+
+pub fn parse_mode(value: &str) -> Option<TicModeEnum> {
+    match value.to_lowercase().as_str() {
+        "standard" => Some(TicModeEnum::Standard),
+        "historique" => Some(TicModeEnum::Historique),
+        _ => None,
+    }
+}
+
+pub fn parse_log_level(value: &str) -> Option<log::LevelFilter> {
+    match value.to_lowercase().as_str() {
+        "off" => Some(log::LevelFilter::Off),
+        "error" => Some(log::LevelFilter::Error),
+        "warn" => Some(log::LevelFilter::Warn),
+        "info" => Some(log::LevelFilter::Info),
+        "debug" => Some(log::LevelFilter::Debug),
+        "trace" => Some(log::LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+fn check_baudrate(setting: &Setting) -> Result<u32, String> {
+    setting.value.parse().ok().filter(|b| *b > 0).ok_or_else(|| setting.invalid("a positive serial speed, e.g. 1200 or 9600"))
+}
+
+fn check_replay_speed(setting: &Setting) -> Result<f64, String> {
+    setting.value.parse().ok().filter(|s: &f64| s.is_finite() && *s >= 0.0)
+        .ok_or_else(|| setting.invalid("a pace factor, 1 for the original pace or 0 for none"))
+}
+
+fn check_power_window(setting: &Setting) -> Result<Duration, String> {
+    setting.value.parse().ok().and_then(|s: f64| Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(|| setting.invalid("a duration in seconds, 0 to disable the power estimate"))
+}
+
+fn check_csv_interval(setting: &Setting) -> Result<Duration, String> {
+    setting.value.parse().ok().and_then(|s: f64| Duration::try_from_secs_f64(s).ok())
+        .filter(|d| d.is_zero() || d.as_secs() >= 1)
+        .ok_or_else(|| setting.invalid("a duration in seconds, at least 1, or 0 for every frame"))
+}
+
+fn check_days(setting: &Setting) -> Result<Duration, String> {
+    setting.value.parse().ok().and_then(|d: u64| d.checked_mul(86400)).map(Duration::from_secs)
+        .ok_or_else(|| setting.invalid("a number of days, 0 to keep for ever"))
+}
+
+fn check_listen(setting: &Setting) -> Result<String, String> {
+    match setting.value.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(setting.value.clone()),
+        _ => Err(setting.invalid("an address and a port, e.g. 0.0.0.0:9100")),
+    }
+}
+
+fn check_meter_id(setting: &Setting) -> Result<String, String> {
+    if setting.value.is_empty() || !setting.value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(setting.invalid("a name made of letters, digits, '_' and '-'"));
+    }
+    // <prefix>/status is the availability of the bridge
+    if setting.value == "status" {
+        return Err(setting.invalid("a name other than 'status', reserved for the bridge availability topic"));
+    }
+    Ok(setting.value.clone())
+}
+
+fn check_topic(setting: &Setting) -> Result<String, String> {
+    if setting.value.is_empty() || setting.value.contains(['+', '#']) {
+        return Err(setting.invalid("a topic without wildcards"));
+    }
+    Ok(setting.value.clone())
+}
+
+/// Render a tariff as the `table` TOML table
+fn push_tariff(out: &mut String, table: &str, tariff: &Tariff) {
+    out.push_str(&format!("\n[{}]\n", table));
+    out.push_str(&format!("subscription = {:?}\n", tariff.subscription));
+    out.push_str(&format!("\n[{}.prices]\n", table));
+    for (label, price) in &tariff.prices {
+        out.push_str(&format!("{} = {:?}\n", label, price));
+    }
+}
+
+/// Configuration file given by --config or CONFIG_FILE, and its content
+fn load_file(config: Option<String>) -> Result<(Option<String>, FileConfig), String> {
+    let path = config.or_else(|| std::env::var("CONFIG_FILE").ok().filter(|v| !v.is_empty()));
+    let file = match &path {
+        Some(path) => FileConfig::load(path)?,
+        None => FileConfig::default(),
+    };
+    Ok((path, file))
+}
+
+/// Address of the REST API alone, for --healthcheck, which runs without the other settings of the bridge
+pub fn healthcheck_listen(opt: Opt) -> Result<String, String> {
+    let (path, file) = load_file(opt.config)?;
+    let r = Resolver { file: path.as_deref() };
+    let listen = r.get(opt.http_listen, "http-listen", "HTTP_LISTEN", file.http.listen, "http.listen")
+        .ok_or("--healthcheck requires --http-listen")?;
+    check_listen(&listen)
+}
+
+impl Config {
+    pub fn from_opt(opt: Opt) -> Result<Config, String> {
+        let (path, file) = load_file(opt.config)?;
+        let r = Resolver { file: path.as_deref() };
+
+        // Default mode, also used by [[meters]] entries without their own
+        let mode = r.get_or(opt.mode, "mode", "TIC_MODE", file.serial.mode, "serial.mode", "standard");
+        let mode = parse_mode(&mode.value).ok_or_else(|| mode.invalid("standard or historique"))?;
+
+        // Default replay pace, also used by [[meters]] entries without their own
+        let replay_speed = r.get_or(opt.replay_speed, "replay-speed", "REPLAY_SPEED", file.serial.replay_speed.map(|s| s.to_string()), "serial.replay_speed", "0");
+        let replay_speed = check_replay_speed(&replay_speed)?;
+
+        // Tariffs of the [[meters]] entries, by index
+        let mut meter_tariffs: Vec<(usize, Option<FileTariff>)> = Vec::new();
+        let mut meters = if file.meters.is_empty() {
+            if opt.serial.is_some() && opt.replay.is_some() {
+                return Err("--serial and --replay are mutually exclusive".to_string());
+            }
+            let replay = r.get(opt.replay, "replay", "REPLAY_FILE", file.serial.replay, "serial.replay");
+            let serial = r.get_or(opt.serial, "serial", "SERIAL_PORT", file.serial.port, "serial.port", "/dev/ttyUSB0").value;
+            let baudrate = r.get(opt.baudrate, "baudrate", "SERIAL_BAUDRATE", file.serial.baudrate.map(|b| b.to_string()), "serial.baudrate");
+            let record = r.get(opt.record, "record", "RECORD_FILE", file.serial.record, "serial.record");
+            if let (Some(record), Some(replay)) = (&record, &replay) {
+                return Err(format!("{} cannot be used with {}", record.origin, replay.origin));
+            }
+            let id = r.get(opt.meter_id, "meter-id", "METER_ID", file.serial.id, "serial.id");
+            vec![MeterConfig {
+                id: id.as_ref().map(check_meter_id).transpose()?,
+                input: match replay {
+                    Some(replay) => Input::Replay { path: replay.check_file()?, speed: replay_speed },
+                    None if serial == "-" => Input::Stdin,
+                    None => Input::Serial(serial),
+                },
+                record: record.map(|r| r.value),
+                mode: mode.clone(),
+                baudrate: baudrate.as_ref().map(check_baudrate).transpose()?,
+                tariff: None,
+            }]
+        } else {
+            // The meters list replaces the single meter settings, SERIAL_PORT and friends are ignored
+            for (value, option) in [(&opt.serial, "--serial"), (&opt.baudrate, "--baudrate"), (&opt.meter_id, "--meter-id"), (&opt.replay, "--replay"), (&opt.record, "--record")] {
+                if value.is_some() {
+                    return Err(format!("{} cannot be used with [[meters]] in {}", option, path.as_deref().unwrap_or("config file")));
+                }
+            }
+            let mut meters: Vec<MeterConfig> = Vec::new();
+            for (i, m) in file.meters.into_iter().enumerate() {
+                let setting = |key: &str, value: String| Setting {
+                    value,
+                    origin: format!("meters[{}].{} in {}", i, key, path.as_deref().unwrap_or("config file")),
+                    rank: 2,
+                };
+                if m.record.is_some() && m.replay.is_some() {
+                    return Err(format!("meters[{}] in {}: record cannot be used with replay", i, path.as_deref().unwrap_or("config file")));
+                }
+                let input = match (m.port, m.replay) {
+                    (Some(_), Some(_)) => return Err(format!("meters[{}] in {}: port and replay are mutually exclusive", i, path.as_deref().unwrap_or("config file"))),
+                    (None, None) => return Err(format!("meters[{}] in {}: missing port or replay", i, path.as_deref().unwrap_or("config file"))),
+                    (Some(port), None) if port == "-" => Input::Stdin,
+                    (Some(port), None) => Input::Serial(port),
+                    (None, Some(replay)) => Input::Replay {
+                        path: setting("replay", replay).check_file()?,
+                        speed: match m.replay_speed {
+                            Some(speed) => check_replay_speed(&setting("replay_speed", speed.to_string()))?,
+                            None => replay_speed,
+                        },
+                    },
+                };
+                let meter = MeterConfig {
+                    id: m.id.map(|id| check_meter_id(&setting("id", id))).transpose()?,
+                    input,
+                    record: m.record,
+                    mode: match m.mode {
+                        Some(value) => { let s = setting("mode", value); parse_mode(&s.value).ok_or_else(|| s.invalid("standard or historique"))? },
+                        None => mode.clone(),
+                    },
+                    baudrate: m.baudrate.map(|b| check_baudrate(&setting("baudrate", b.to_string()))).transpose()?,
+                    tariff: None,
+                };
+                // Checked with the global tariff
+                meter_tariffs.push((i, m.tariff));
+                if let Some(other) = meters.iter().find(|other| matches!(other.input, Input::Serial(_) | Input::Stdin) && other.input == meter.input) {
+                    let port = match &other.input { Input::Serial(port) => port.as_str(), _ => "-" };
+                    return Err(format!("meters[{}].port in {}: serial port '{}' is used by several meters", i, path.as_deref().unwrap_or("config file"), port));
+                }
+                if meter.record.is_some() && meters.iter().any(|other| other.record == meter.record) {
+                    return Err(format!("meters[{}].record in {}: capture file '{}' is used by several meters", i, path.as_deref().unwrap_or("config file"), meter.record.as_deref().unwrap_or_default()));
+                }
+                if meter.id.is_some() && meters.iter().any(|other| other.id == meter.id) {
+                    return Err(format!("meters[{}].id in {}: meter id '{}' is used by several meters", i, path.as_deref().unwrap_or("config file"), meter.id.as_deref().unwrap_or_default()));
+                }
+                meters.push(meter);
+            }
+            meters
+        };
+
+        let qos = r.get_or(opt.mqtt_qos, "mqtt-qos", "MQTT_QOS", file.mqtt.qos.map(|q| q.to_string()), "mqtt.qos", "1");
+        let qos = match qos.value.as_str() {
+            "0" | "1" | "2" => qos.value.parse().unwrap_or(1),
+            _ => return Err(qos.invalid("0, 1 or 2")),
+        };
+
+        let discovery = r.get_or(opt.discovery, "discovery", "DISCOVERY", file.discovery.policy, "discovery.policy", "all");
+        let discovery = DiscoveryPolicy::parse(&discovery.value).ok_or_else(|| discovery.invalid("all, seen or none"))?;
+
+        let power_window = r.get_or(opt.power_window, "power-window", "POWER_WINDOW", file.derived.power_window.map(|w| w.to_string()), "derived.power_window", "60");
+        let state_file = r.get(opt.state_file, "state-file", "STATE_FILE", file.derived.state_file, "derived.state_file").map(|s| s.value);
+        let production_topic = r.get(opt.production_topic, "production-topic", "PRODUCTION_TOPIC", file.derived.production_topic, "derived.production_topic");
+        let metrics_listen = r.get(opt.metrics_listen, "metrics-listen", "METRICS_LISTEN", file.metrics.listen, "metrics.listen")
+            .map(|s| check_listen(&s))
+            .transpose()?;
+        let http_listen = r.get(opt.http_listen, "http-listen", "HTTP_LISTEN", file.http.listen, "http.listen")
+            .map(|s| check_listen(&s))
+            .transpose()?;
+        let influx_token = secret(
+            r.get(opt.influx_token, "influx-token", "INFLUX_TOKEN", file.influx.token, "influx.token"),
+            r.get(opt.influx_token_file, "influx-token-file", "INFLUX_TOKEN_FILE", file.influx.token_file, "influx.token_file"),
+        )?;
+        let influx = match r.get(opt.influx_url, "influx-url", "INFLUX_URL", file.influx.url, "influx.url") {
+            Some(url) => {
+                let target = InfluxTarget::parse(&url.value)
+                    .ok_or_else(|| url.invalid("udp://HOST:PORT, http://HOST:PORT/PATH?QUERY or file://PATH"))?;
+                Some(InfluxConfig { url: url.value, target, token: influx_token })
+            }
+            None => None,
+        };
+        let json_output = r.get(opt.json_output, "json-output", "JSON_OUTPUT", file.json.path, "json.path").map(|s| s.value);
+        let csv_interval = r.get_or(opt.csv_interval, "csv-interval", "CSV_INTERVAL", file.csv.interval.map(|i| i.to_string()), "csv.interval", "0");
+        let csv = match r.get(opt.csv_dir, "csv-dir", "CSV_DIR", file.csv.dir, "csv.dir") {
+            Some(dir) if !std::path::Path::new(&dir.value).is_dir() => return Err(format!("directory '{}' from {} does not exist", dir.value, dir.origin)),
+            Some(dir) => Some(CsvConfig { dir: dir.value, interval: check_csv_interval(&csv_interval)? }),
+            None => None,
+        };
+        let sqlite = match r.get(opt.sqlite_path, "sqlite-path", "SQLITE_PATH", file.sqlite.path, "sqlite.path") {
+            Some(path) => {
+                let parent = std::path::Path::new(&path.value).parent().filter(|p| !p.as_os_str().is_empty());
+                if parent.is_some_and(|p| !p.is_dir()) {
+                    return Err(format!("directory of '{}' from {} does not exist", path.value, path.origin));
+                }
+                let retention = r.get_or(opt.sqlite_retention, "sqlite-retention", "SQLITE_RETENTION", file.sqlite.retention.map(|d| d.to_string()), "sqlite.retention", "2");
+                let minute_retention = r.get_or(opt.sqlite_minute_retention, "sqlite-minute-retention", "SQLITE_MINUTE_RETENTION", file.sqlite.minute_retention.map(|d| d.to_string()), "sqlite.minute_retention", "365");
+                Some(SqliteConfig { path: path.value, retention: check_days(&retention)?, minute_retention: check_days(&minute_retention)? })
+            }
+            None => None,
+        };
+
+        let mut default_outputs = vec!["mqtt"];
+        default_outputs.extend(influx.as_ref().map(|_| "influx"));
+        default_outputs.extend(json_output.as_ref().map(|_| "json"));
+        default_outputs.extend(csv.as_ref().map(|_| "csv"));
+        default_outputs.extend(sqlite.as_ref().map(|_| "sqlite"));
+        let outputs = r.get_or(opt.outputs, "outputs", "OUTPUTS", file.outputs.map(|o| o.join(",")), "outputs", &default_outputs.join(","));
+        let mut kinds = Vec::new();
+        for value in outputs.value.split(',') {
+            let kind = OutputKind::parse(value).ok_or_else(|| outputs.invalid("a list of outputs among mqtt, influx, json, csv and sqlite"))?;
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        if kinds.contains(&OutputKind::Influx) && influx.is_none() {
+            return Err(format!("{}: the influx output requires --influx-url", outputs.origin));
+        }
+        if kinds.contains(&OutputKind::Json) && json_output.is_none() {
+            return Err(format!("{}: the json output requires --json-output", outputs.origin));
+        }
+        if kinds.contains(&OutputKind::Csv) && csv.is_none() {
+            return Err(format!("{}: the csv output requires --csv-dir", outputs.origin));
+        }
+        if kinds.contains(&OutputKind::Sqlite) && sqlite.is_none() {
+            return Err(format!("{}: the sqlite output requires --sqlite-path", outputs.origin));
+        }
+        // Received through the MQTT connection
+        let production_topic = match production_topic {
+            Some(topic) if !kinds.contains(&OutputKind::Mqtt) => return Err(format!("{} requires the mqtt output", topic.origin)),
+            Some(topic) => Some(check_topic(&topic)?),
+            None => None,
+        };
+
+        let log_level = r.get_or(opt.log_level, "log-level", "LOG_LEVEL", file.log_level, "log_level", "info");
+        let log_level = parse_log_level(&log_level.value)
+            .ok_or_else(|| log_level.invalid("off, error, warn, info, debug or trace"))?;
+
+        let topics = Topics {
+            prefix: check_topic(&r.get_or(opt.mqtt_topic_prefix, "mqtt-topic-prefix", "MQTT_TOPIC_PREFIX", file.mqtt.topic_prefix, "mqtt.topic_prefix", MQTT_ID_BASE))?,
+            discovery_prefix: check_topic(&r.get_or(opt.discovery_prefix, "discovery-prefix", "DISCOVERY_PREFIX", file.discovery.prefix, "discovery.prefix", MQTT_DISCOVERY_PREFIX))?,
+        };
+
+        let server = r.get_or(opt.mqtt_server, "mqtt-server", "MQTT_SERVER", file.mqtt.server, "mqtt.server", "tcp://localhost:1883");
+        let password = secret(
+            r.get(opt.mqtt_pass, "mqtt-pass", "MQTT_PASS", file.mqtt.pass, "mqtt.pass"),
+            r.get(opt.mqtt_pass_file, "mqtt-pass-file", "MQTT_PASS_FILE", file.mqtt.pass_file, "mqtt.pass_file"),
+        )?;
+
+        // TLS is used with ssl:// or mqtts:// servers
+        let tls = TlsConfig {
+            ca: r.get(opt.mqtt_ca, "mqtt-ca", "MQTT_CA", file.mqtt.ca, "mqtt.ca").map(Setting::check_file).transpose()?,
+            cert: r.get(opt.mqtt_cert, "mqtt-cert", "MQTT_CERT", file.mqtt.cert, "mqtt.cert").map(Setting::check_file).transpose()?,
+            key: r.get(opt.mqtt_key, "mqtt-key", "MQTT_KEY", file.mqtt.key, "mqtt.key").map(Setting::check_file).transpose()?,
+            key_password: secret(
+                r.get(opt.mqtt_key_pass, "mqtt-key-pass", "MQTT_KEY_PASS", file.mqtt.key_pass, "mqtt.key_pass"),
+                r.get(opt.mqtt_key_pass_file, "mqtt-key-pass-file", "MQTT_KEY_PASS_FILE", file.mqtt.key_pass_file, "mqtt.key_pass_file"),
+            )?,
+        };
+        let secure = server.value.starts_with("ssl://") || server.value.starts_with("mqtts://");
+        let tls = match (secure, tls.ca.is_some() || tls.cert.is_some() || tls.key.is_some() || !tls.key_password.is_empty()) {
+            (true, _) => Some(tls),
+            (false, true) => return Err(server.invalid("an ssl:// or mqtts:// address when TLS options are set")),
+            (false, false) => None,
+        };
+
+        let mqtt = MqttConfig {
+            server: server.value,
+            client_id: r.get_or(opt.mqtt_client_id, "mqtt-client-id", "MQTT_CLIENT_ID", file.mqtt.client_id, "mqtt.client_id", "tic2mqtt_client").value,
+            username: r.get_or(opt.mqtt_user, "mqtt-user", "MQTT_USER", file.mqtt.user, "mqtt.user", "").value,
+            password,
+            tls,
+            qos,
+            topics,
+            discovery,
+        };
+
+        // Label overrides and prices only come from the file, check them against the modes in use and the derived metrics
+        let known: Vec<String> = meters.iter().flat_map(|m| TicModeHandle::new(m.mode.clone()).labels()).collect();
+        let file_origin = |key: &str| format!("{} in {}", key, path.as_deref().unwrap_or("config file"));
+        // Prices of the labels of `modes`, under `table`
+        let parse_tariff = |t: FileTariff, table: &str, labels: &[String], modes: &str| -> Result<Tariff, String> {
+            let amount = |key: String, value: f64| -> Result<f64, String> {
+                if value.is_finite() && value >= 0.0 { return Ok(value); }
+                Err(Setting { value: value.to_string(), origin: file_origin(&key), rank: 2 }.invalid("a positive amount in EUR"))
+            };
+            let subscription = amount(format!("{}.subscription", table), t.subscription)?;
+            let mut prices = BTreeMap::new();
+            for (label, price) in t.prices {
+                let key = format!("{}.prices.{}", table, label);
+                if !labels.contains(&label) {
+                    return Err(format!("{}: unknown label for {} mode", file_origin(&key), modes));
+                }
+                prices.insert(label, amount(key, price)?);
+            }
+            Ok(Tariff { prices, subscription })
+        };
+        let modes: Vec<&str> = meters.iter().map(|m| mode_name(&m.mode)).collect();
+        let tariff = file.tariff.map(|t| parse_tariff(t, "tariff", &known, &modes.join("/"))).transpose()?;
+        for (i, t) in meter_tariffs {
+            if let Some(t) = t {
+                let labels = TicModeHandle::new(meters[i].mode.clone()).labels();
+                meters[i].tariff = Some(parse_tariff(t, &format!("meters[{}].tariff", i), &labels, mode_name(&meters[i].mode))?);
+            }
+        }
+        // The global tariff applies to every meter, whatever its mode
+        let mut warnings = Vec::new();
+        for (i, meter) in meters.iter().enumerate() {
+            let Some(tariff) = tariff.as_ref().filter(|_| meter.tariff.is_none()) else { continue };
+            let labels = TicModeHandle::new(meter.mode.clone()).labels();
+            let ignored: Vec<&str> = tariff.prices.keys().filter(|l| !labels.contains(l)).map(String::as_str).collect();
+            if !ignored.is_empty() {
+                warnings.push(format!(
+                    "{}: {} never sent by meter {} in {} mode, whose costs ignore them; set its own prices in [meters.tariff]",
+                    file_origin("tariff.prices"), ignored.join(", "), meter.id.clone().unwrap_or_else(|| format!("meters[{}]", i)), mode_name(&meter.mode)
+                ));
+            }
+        }
+        let derived = DerivedConfig { power_window: check_power_window(&power_window)?, state_file, production_topic, tariff };
+
+        let mut labels = HashMap::new();
+        for (label, o) in file.labels {
+            let key = format!("labels.{}", label);
+            let origin = file_origin(&key);
+            if !known.contains(&label) && metric_info(&label).is_none() {
+                let modes: Vec<&str> = meters.iter().map(|m| mode_name(&m.mode)).collect();
+                return Err(format!("{}: unknown label for {} mode", origin, modes.join("/")));
+            }
+            if let Some(sc) = o.state_class.as_ref().filter(|sc| !["", "measurement", "total", "total_increasing"].contains(&sc.as_str())) {
+                let setting = Setting { value: sc.clone(), origin: file_origin(&format!("{}.state_class", key)), rank: 2 };
+                return Err(setting.invalid("measurement, total or total_increasing"));
+            }
+            labels.insert(label, LabelOverride {
+                name: o.name,
+                device_class: o.device_class,
+                state_class: o.state_class,
+                unit: o.unit,
+                enabled: o.enabled,
+            });
+        }
+
+        Ok(Config { file: path, meters, warnings, mqtt, log_level, labels, derived, metrics_listen, http_listen, outputs: kinds, influx, json_output, csv, sqlite })
+    }
+
+    /// Render the configuration as TOML, with secrets redacted
+    pub fn to_redacted_string(&self) -> String {
+        let password = if self.mqtt.password.is_empty() { "" } else { "********" };
+        let mut out = String::new();
+        if let Some(file) = &self.file {
+            out.push_str(&format!("# merged with {}\n", file));
+        }
+        out.push_str(&format!("log_level = {:?}\n", self.log_level.as_str().to_lowercase()));
+        let outputs: Vec<String> = self.outputs.iter().map(|o| format!("{:?}", o.as_str())).collect();
+        out.push_str(&format!("outputs = [{}]\n", outputs.join(", ")));
+        for meter in &self.meters {
+            out.push_str("\n[[meters]]\n");
+            if let Some(id) = &meter.id {
+                out.push_str(&format!("id = {:?}\n", id));
+            }
+            match &meter.input {
+                Input::Serial(port) => out.push_str(&format!("port = {:?}\n", port)),
+                Input::Stdin => out.push_str("port = \"-\"\n"),
+                Input::Replay { path, speed } => out.push_str(&format!("replay = {:?}\nreplay_speed = {}\n", path, speed)),
+            }
+            if let Some(record) = &meter.record {
+                out.push_str(&format!("record = {:?}\n", record));
+            }
+            out.push_str(&format!("mode = {:?}\n", mode_name(&meter.mode)));
+            if let Some(baudrate) = meter.baudrate {
+                out.push_str(&format!("baudrate = {}\n", baudrate));
+            }
+            if let Some(tariff) = &meter.tariff {
+                push_tariff(&mut out, "meters.tariff", tariff);
+            }
+        }
+        out.push_str("\n[mqtt]\n");
+        out.push_str(&format!("server = {:?}\n", self.mqtt.server));
+        out.push_str(&format!("client_id = {:?}\n", self.mqtt.client_id));
+        out.push_str(&format!("user = {:?}\n", self.mqtt.username));
+        out.push_str(&format!("pass = {:?}\n", password));
+        out.push_str(&format!("qos = {}\n", self.mqtt.qos));
+        out.push_str(&format!("topic_prefix = {:?}\n", self.mqtt.topics.prefix));
+        if let Some(tls) = &self.mqtt.tls {
+            for (key, value) in [("ca", &tls.ca), ("cert", &tls.cert), ("key", &tls.key)] {
+                if let Some(value) = value {
+                    out.push_str(&format!("{} = {:?}\n", key, value));
+                }
+            }
+            if !tls.key_password.is_empty() {
+                out.push_str("key_pass = \"********\"\n");
+            }
+        }
+        out.push_str("\n[derived]\n");
+        out.push_str(&format!("power_window = {}\n", self.derived.power_window.as_secs_f64()));
+        if let Some(state_file) = &self.derived.state_file {
+            out.push_str(&format!("state_file = {:?}\n", state_file));
+        }
+        if let Some(topic) = &self.derived.production_topic {
+            out.push_str(&format!("production_topic = {:?}\n", topic));
+        }
+        if let Some(tariff) = &self.derived.tariff {
+            push_tariff(&mut out, "tariff", tariff);
+        }
+        if let Some(listen) = &self.metrics_listen {
+            out.push_str(&format!("\n[metrics]\nlisten = {:?}\n", listen));
+        }
+        if let Some(listen) = &self.http_listen {
+            out.push_str(&format!("\n[http]\nlisten = {:?}\n", listen));
+        }
+        if let Some(influx) = &self.influx {
+            out.push_str(&format!("\n[influx]\nurl = {:?}\n", influx.url));
+            if !influx.token.is_empty() {
+                out.push_str("token = \"********\"\n");
+            }
+        }
+        if let Some(path) = &self.json_output {
+            out.push_str(&format!("\n[json]\npath = {:?}\n", path));
+        }
+        if let Some(csv) = &self.csv {
+            out.push_str(&format!("\n[csv]\ndir = {:?}\ninterval = {}\n", csv.dir, csv.interval.as_secs_f64()));
+        }
+        if let Some(sqlite) = &self.sqlite {
+            out.push_str(&format!(
+                "\n[sqlite]\npath = {:?}\nretention = {}\nminute_retention = {}\n",
+                sqlite.path, sqlite.retention.as_secs() / 86400, sqlite.minute_retention.as_secs() / 86400
+            ));
+        }
+        out.push_str("\n[discovery]\n");
+        out.push_str(&format!("policy = {:?}\n", self.mqtt.discovery.as_str()));
+        out.push_str(&format!("prefix = {:?}\n", self.mqtt.topics.discovery_prefix));
+        let labels: BTreeMap<_, _> = self.labels.iter().collect();
+        for (label, o) in labels {
+            // Bare keys only allow A-Za-z0-9_-, quote the others (e.g. "NJOURF+1")
+            if label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                out.push_str(&format!("\n[labels.{}]\n", label));
+            } else {
+                out.push_str(&format!("\n[labels.{:?}]\n", label));
+            }
+            for (key, value) in [("name", &o.name), ("device_class", &o.device_class), ("state_class", &o.state_class), ("unit", &o.unit)] {
+                if let Some(value) = value {
+                    out.push_str(&format!("{} = {:?}\n", key, value));
+                }
+            }
+            if let Some(enabled) = o.enabled {
+                out.push_str(&format!("enabled = {}\n", enabled));
+            }
+        }
+        out
+    }
+}
+
+fn mode_name(mode: &TicModeEnum) -> &'static str {
+    match mode {
+        TicModeEnum::Standard => "standard",
+        TicModeEnum::Historique => "historique",
+    }
+}
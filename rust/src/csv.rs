@@ -0,0 +1,119 @@
+//! CSV output: one row per frame, or per sampling interval, in a file per meter and per day.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+use tic2mqtt::tic::Value;
+use tic2mqtt::utils::{civil_from_days, format_timestamp, local_day};
+
+use crate::meter::{FrameUpdate, Meter, Update};
+use crate::output::{OutputSink, Updates};
+
+/// Settings of the CSV output
+pub struct CsvConfig {
+    /// Directory of the files
+    pub dir: String,
+    /// One row per interval, one row per frame if zero
+    pub interval: Duration,
+}
+
+/// Quote a cell containing a separator, a double quote or a line break
+fn cell(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Open file of a meter, for the day of its last row
+struct MeterFile {
+    day: i64,
+    file: File,
+    /// Sampling interval of the last row
+    slot: Option<u64>,
+}
+
+/// Writes the frames of each meter to <dir>/<meter>-<YYYY-MM-DD>.csv, a new file each local day. The columns are
+/// the time of reception, the meter date, every label of the TIC mode but DATE, and the derived metrics configured.
+pub struct CsvSink {
+    dir: String,
+    /// One row per interval, the first frame of each, one row per frame if zero
+    interval: Duration,
+    /// Columns of each meter, after the times
+    columns: Vec<Vec<String>>,
+    files: HashMap<usize, MeterFile>,
+}
+
+impl CsvSink {
+    pub fn new(dir: String, meters: &[Meter]) -> Self {
+        let columns = meters.iter()
+            .map(|meter| {
+                let mut columns: Vec<String> = meter.mode.labels().into_iter().filter(|l| l != "DATE").collect();
+                columns.extend(meter.derived.lock().unwrap_or_else(|e| e.into_inner()).ids().iter().map(|id| id.to_string()));
+                columns
+            })
+            .collect();
+        CsvSink { dir, interval: Duration::ZERO, columns, files: HashMap::new() }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self { self.interval = interval; self }
+
+    fn write(&mut self, index: usize, frame: &FrameUpdate) -> io::Result<()> {
+        let secs = frame.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let slot = secs.checked_div(self.interval.as_secs()).unwrap_or(secs);
+        let day = local_day(frame.time);
+        let file = self.files.get_mut(&index).filter(|f| f.day == day);
+        if file.as_ref().is_some_and(|f| f.slot == Some(slot) && !self.interval.is_zero()) {
+            return Ok(());
+        }
+        let file = match file {
+            Some(file) => file,
+            None => {
+                let (year, month, date) = civil_from_days(day);
+                let path = format!("{}/{}-{:04}-{:02}-{:02}.csv", self.dir, frame.meter, year, month, date);
+                let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+                if file.metadata()?.len() == 0 {
+                    let header: Vec<String> = self.columns[index].iter().map(|c| cell(c)).collect();
+                    writeln!(file, "timestamp,date,{}", header.join(","))?;
+                }
+                log::debug!("[CSV] writing to {}", path);
+                self.files.entry(index).insert_entry(MeterFile { day, file, slot: None }).into_mut()
+            }
+        };
+        let mut row = vec![format_timestamp(frame.time), frame.date.clone().unwrap_or_default()];
+        for column in &self.columns[index] {
+            let value = frame.values.iter().map(|(l, v)| (l, v))
+                .chain(frame.metrics.iter().map(|m| (&m.id, &m.value)))
+                .find(|(label, _)| *label == column)
+                .map(|(_, value)| value);
+            row.push(match value {
+                Some(Value::Float(v)) if !v.is_finite() => String::new(),
+                Some(Value::Text(s)) => cell(s.trim()),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            });
+        }
+        writeln!(file.file, "{}", row.join(","))?;
+        file.slot = Some(slot);
+        Ok(())
+    }
+}
+
+impl OutputSink for CsvSink {
+    fn name(&self) -> &'static str { "CSV" }
+
+    /// Write the frames, alert events are not written
+    fn run(&mut self, updates: Updates) {
+        for (index, update) in updates {
+            let Update::Frame(frame) = update else { continue };
+            if let Err(e) = self.write(index, &frame) {
+                log::warn!("[CSV] {}: {}", self.dir, e);
+                // Reopened on the next frame
+                self.files.remove(&index);
+            }
+        }
+    }
+}
@@ -0,0 +1,460 @@
+//! Metrics derived from the successive frames of a meter, e.g. the active power from its energy indexes.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tic::{TicMode, Value};
+use crate::utils::{civil_from_days, days_from_civil, local_day};
+
+/// Home Assistant metadata of a derived metric
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricInfo {
+    pub name: String,
+    pub device_class: Option<&'static str>,
+    pub state_class: Option<&'static str>,
+    pub unit: Option<&'static str>,
+}
+
+/// Suffixes of the consumption counters of an index: (current period, previous period)
+const COUNTERS: [(Period, &str, &str); 3] = [
+    (Period::Day, "today", "yesterday"),
+    (Period::Week, "this_week", "last_week"),
+    (Period::Month, "this_month", "last_month"),
+];
+
+/// Metadata of a derived metric: "active_power", "TOTAL", a cost, or a counter such as "HCHC_today", "NET_today" or "SELF_today"
+pub fn metric_info(id: &str) -> Option<MetricInfo> {
+    let info = |name: &str, device_class, state_class, unit| Some(MetricInfo { name: name.to_string(), device_class, state_class, unit });
+    match id {
+        "active_power" => return info("active power", Some("power"), Some("measurement"), Some("W")),
+        "TOTAL" => return info("total energy", Some("energy"), Some("total_increasing"), Some("Wh")),
+        "return_power" => return info("return power", Some("power"), Some("measurement"), Some("W")),
+        "net_power" => return info("net power", Some("power"), Some("measurement"), Some("W")),
+        "rate" => return info("current rate", None, None, Some("EUR/kWh")),
+        "cost_today" => return info("cost today", Some("monetary"), Some("total"), Some("EUR")),
+        "cost_this_month" => return info("cost this month", Some("monetary"), Some("total"), Some("EUR")),
+        _ => {}
+    }
+    for (_, current, previous) in COUNTERS {
+        for (suffix, state_class) in [(current, Some("total_increasing")), (previous, None)] {
+            if let Some(index) = id.strip_suffix(suffix).and_then(|i| i.strip_suffix('_')).filter(|i| !i.is_empty()) {
+                // The net energy goes down while returning to the grid, the self-consumption while the production index lags
+                let state_class = if index == "NET" || index == "SELF" { state_class.and(Some("total")) } else { state_class };
+                let name = match index {
+                    "SELF" => "self-consumption",
+                    "PRODUCTION" => "production",
+                    index => index,
+                };
+                return info(&format!("{} {}", name, suffix.replace('_', " ")), Some("energy"), state_class, Some("Wh"));
+            }
+        }
+    }
+    None
+}
+
+/// Value of a derived metric
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub id: String,
+    pub value: Value,
+}
+
+/// Energy drawn since the start, from the deltas of the consumption indexes.
+///
+/// The sum of the deltas does not depend on which index is active, and an index wrapping
+/// around (e.g. 999999999 to 000000000) still counts forward.
+#[derive(Default)]
+struct EnergyCounter {
+    indexes: HashMap<String, i64>,
+    total: i64,
+}
+
+impl EnergyCounter {
+    /// Add the deltas of the indexes, false when an index went backwards (meter replaced, bad value)
+    fn update(&mut self, indexes: &[(&str, &str)]) -> bool {
+        let mut ok = true;
+        for (label, raw) in indexes {
+            let Ok(value) = raw.parse::<i64>() else { continue };
+            let modulus = modulus(raw);
+            match self.indexes.insert(label.to_string(), value) {
+                Some(previous) if value >= previous => self.total += value - previous,
+                Some(previous) if previous - value > modulus / 2 => self.total += value + modulus - previous,
+                Some(_) => ok = false,
+                // A new index is a starting point
+                None => {}
+            }
+        }
+        ok
+    }
+}
+
+/// Indexes wrap around at their number of digits
+fn modulus(raw: &str) -> i64 {
+    10i64.saturating_pow(raw.len() as u32)
+}
+
+/// Energy from the index `start` to `value`, across a wrap around; none if the index went back, e.g. on a
+/// replaced meter or a state file of another meter
+fn delta(start: i64, raw: &str, value: i64) -> Option<i64> {
+    match value - start {
+        delta if delta >= 0 => Some(delta),
+        delta if -delta > modulus(raw) / 2 => Some(delta + modulus(raw)),
+        _ => None,
+    }
+}
+
+/// Average power over a sliding window of (time, energy) samples
+struct PowerEstimator {
+    window: Duration,
+    samples: VecDeque<(Instant, i64)>,
+}
+
+impl PowerEstimator {
+    fn push(&mut self, time: Instant, energy: i64) {
+        self.samples.push_back((time, energy));
+        // Keep one sample at or before the start of the window
+        while self.samples.len() > 2 && time.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Power in W, once the samples span the window
+    fn power(&self) -> Option<i64> {
+        let (start, first) = self.samples.front()?;
+        let (end, last) = self.samples.back()?;
+        let elapsed = end.duration_since(*start);
+        if elapsed < self.window || elapsed.is_zero() { return None; }
+        Some(((last - first) as f64 * 3600.0 / elapsed.as_secs_f64()).round() as i64)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Period {
+    Day,
+    /// From Monday
+    Week,
+    Month,
+}
+
+impl Period {
+    /// First day of the period containing `day`, in days since 1970-01-01
+    fn start(self, day: i64) -> i64 {
+        match self {
+            Period::Day => day,
+            // 1970-01-01 is a Thursday
+            Period::Week => day - (day + 3).rem_euclid(7),
+            Period::Month => {
+                let (year, month, _) = civil_from_days(day);
+                days_from_civil(year, month, 1)
+            }
+        }
+    }
+
+    /// First day of the period following the one starting on `start`
+    fn next(self, start: i64) -> i64 {
+        match self {
+            Period::Day => start + 1,
+            Period::Week => start + 7,
+            Period::Month => start + 31 - (civil_from_days(start + 31).2 as i64 - 1),
+        }
+    }
+}
+
+/// Counter of one period of an index
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeriodCounter {
+    /// First day of the current period, e.g. "2025-01-01"
+    pub since: String,
+    /// Index at the start of the current period
+    pub start: i64,
+    /// Consumption of the previous period, unknown if it was not followed to its end
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<i64>,
+}
+
+/// Counters of an index
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexCounters {
+    pub day: PeriodCounter,
+    pub week: PeriodCounter,
+    pub month: PeriodCounter,
+}
+
+impl IndexCounters {
+    fn period(&mut self, period: Period) -> &mut PeriodCounter {
+        match period {
+            Period::Day => &mut self.day,
+            Period::Week => &mut self.week,
+            Period::Month => &mut self.month,
+        }
+    }
+
+    /// Move to the periods of `today`, and return the consumption of the current and previous
+    /// period of each counter, in the order of COUNTERS, and whether a period changed
+    fn update(&mut self, today: i64, label: &str, raw: &str, value: i64) -> ([Option<i64>; COUNTERS.len() * 2], bool) {
+        let mut consumption = [None; COUNTERS.len() * 2];
+        let mut changed = false;
+        for (i, (period, current, _)) in COUNTERS.into_iter().enumerate() {
+            let counter = self.period(period);
+            let start = period.start(today);
+            let since = parse_date(&counter.since);
+            if since != Some(start) {
+                counter.previous = match since {
+                    Some(since) if period.next(since) == start => delta(counter.start, raw, value),
+                    _ => None,
+                };
+                counter.since = format_date(start);
+                counter.start = value;
+                changed = true;
+            }
+            if delta(counter.start, raw, value).is_none() {
+                log::warn!("{} went back from {} to {}, restarting {}_{}", label, counter.start, value, label, current);
+                counter.start = value;
+                changed = true;
+            }
+            consumption[i * 2] = delta(counter.start, raw, value);
+            consumption[i * 2 + 1] = counter.previous;
+        }
+        (consumption, changed)
+    }
+}
+
+/// Consumption counters of a meter by index, to be saved across restarts
+pub type CounterState = BTreeMap<String, IndexCounters>;
+
+fn format_date(day: i64) -> String {
+    let (year, month, day) = civil_from_days(day);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(days_from_civil(year, u32::try_from(month).ok()?, u32::try_from(day).ok()?))
+}
+
+/// Prices of the energy, to compute the costs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tariff {
+    /// Price of each index, in EUR/kWh, e.g. "HCHC" or "EASF01"; the price of the returned energy (EAIT) is deducted
+    pub prices: BTreeMap<String, f64>,
+    /// Subscription fee, in EUR per month
+    pub subscription: f64,
+}
+
+/// Production index of a producer, in Wh, fed from outside the meter, e.g. by the inverter
+#[derive(Debug, Default)]
+pub struct ProductionIndex(Mutex<Option<i64>>);
+
+impl ProductionIndex {
+    pub fn set(&self, wh: i64) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(wh);
+    }
+
+    /// Last index received, None before
+    pub fn get(&self) -> Option<i64> {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Position of the counters in the consumption of an index
+const TODAY: usize = 0;
+const THIS_MONTH: usize = 4;
+
+/// Derived metrics of one meter, updated on each frame
+#[derive(Default)]
+pub struct Derived {
+    energy: EnergyCounter,
+    power: Option<PowerEstimator>,
+    /// Energy returned to the grid by a producer, and its power
+    returned: EnergyCounter,
+    return_power: Option<PowerEstimator>,
+    /// Sum of the indexes of all the tariff periods
+    total: Option<i64>,
+    counters: CounterState,
+    tariff: Option<Tariff>,
+    production: Option<Arc<ProductionIndex>>,
+    /// The counters moved to a new period since the last call to `counters_changed`
+    changed: bool,
+    metrics: Vec<Metric>,
+}
+
+impl Derived {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Estimate the active power over `window`, none if zero
+    pub fn with_power_window(mut self, window: Duration) -> Self {
+        self.power = (!window.is_zero()).then(|| PowerEstimator { window, samples: VecDeque::new() });
+        self.return_power = (!window.is_zero()).then(|| PowerEstimator { window, samples: VecDeque::new() });
+        self
+    }
+
+    /// Compute the current rate and the costs
+    pub fn with_tariff(mut self, tariff: Tariff) -> Self {
+        self.tariff = Some(tariff);
+        self
+    }
+
+    /// Count the production, and the self-consumption of a producer: the production minus the returned energy (EAIT)
+    pub fn with_production(mut self, production: Arc<ProductionIndex>) -> Self {
+        self.production = Some(production);
+        self
+    }
+
+    /// Metrics always computed, depending on the configuration; the counters depend on the indexes received
+    pub fn ids(&self) -> Vec<&'static str> {
+        let mut ids = Vec::new();
+        if self.power.is_some() { ids.push("active_power"); }
+        ids.push("TOTAL");
+        if self.tariff.is_some() { ids.extend(["rate", "cost_today", "cost_this_month"]); }
+        ids
+    }
+
+    pub fn counters(&self) -> &CounterState {
+        &self.counters
+    }
+
+    /// Restore the counters saved by a previous run
+    pub fn set_counters(&mut self, counters: CounterState) {
+        self.counters = counters;
+    }
+
+    /// Whether the counters changed period since the last call, and should be saved
+    pub fn counters_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Update from the values of the meter after a frame received at `time` (monotonic) and `now` (wall clock).
+    /// The counter periods follow the meter date (DATE) when there is one, else the local date.
+    pub fn update(&mut self, mode: &dyn TicMode, time: Instant, now: SystemTime) {
+        let values = mode.label_values();
+        let labels = mode.energy_indexes();
+        let indexes: Vec<(&str, &str)> = labels.iter()
+            .filter_map(|label| values.get(*label).map(|lv| (*label, lv.value.as_str())))
+            .collect();
+        let ok = self.energy.update(&indexes);
+        // A partial sum would jump when the missing index comes
+        let complete = !labels.is_empty() && indexes.len() == labels.len();
+        self.total = complete.then(|| indexes.iter().map(|(_, raw)| raw.parse::<i64>().ok()).sum::<Option<i64>>()).flatten();
+        if let Some(power) = &mut self.power {
+            if !ok {
+                log::warn!("energy index went backwards, restarting the power estimate");
+                power.samples.clear();
+            }
+            if !indexes.is_empty() {
+                power.push(time, self.energy.total);
+            }
+        }
+
+        let returned = mode.return_index().and_then(|label| values.get(label).map(|lv| (label, lv.value.as_str())));
+        if let Some(index) = returned {
+            let ok = self.returned.update(&[index]);
+            if let Some(power) = &mut self.return_power {
+                if !ok { power.samples.clear(); }
+                power.push(time, self.returned.total);
+            }
+        }
+
+        let today = values.get("DATE")
+            .and_then(|lv| lv.timestamp.as_deref())
+            .and_then(|ts| parse_date(ts.get(..10)?))
+            .unwrap_or_else(|| local_day(now));
+        self.metrics.clear();
+        let power = self.power.as_ref().and_then(|p| p.power());
+        if let Some(power) = power {
+            self.metrics.push(Metric { id: "active_power".to_string(), value: Value::Integer(power) });
+        }
+        // Producers: the power returned to the grid, and the net power, negative while returning
+        if let Some(return_power) = self.return_power.as_ref().filter(|_| returned.is_some()).and_then(|p| p.power()) {
+            self.metrics.push(Metric { id: "return_power".to_string(), value: Value::Integer(return_power) });
+            if let Some(power) = power {
+                self.metrics.push(Metric { id: "net_power".to_string(), value: Value::Integer(power - return_power) });
+            }
+        }
+        if let Some(total) = self.total {
+            self.metrics.push(Metric { id: "TOTAL".to_string(), value: Value::Integer(total) });
+        }
+        // The counters of the energy indexes, summed into the total, of the returned energy, and of the other priced indexes (e.g. EASF01)
+        let mut counted: Vec<(String, String)> = indexes.iter().chain(&returned).map(|(l, v)| (l.to_string(), v.to_string())).collect();
+        // The production, while returning energy
+        if let Some(wh) = self.production.as_ref().filter(|_| returned.is_some()).and_then(|p| p.get()) {
+            counted.push(("PRODUCTION".to_string(), wh.to_string()));
+        }
+        for label in self.tariff.iter().flat_map(|t| t.prices.keys()) {
+            if let Some(lv) = values.get(label).filter(|_| !counted.iter().any(|(l, _)| l == label)) {
+                counted.push((label.clone(), lv.value.clone()));
+            }
+        }
+        let mut totals = [Some(0); COUNTERS.len() * 2];
+        let mut consumptions = HashMap::new();
+        let mut counters = Vec::new();
+        for (label, raw) in &counted {
+            let energy = labels.contains(&label.as_str());
+            let Ok(value) = raw.parse::<i64>() else {
+                if energy { totals.fill(None); }
+                continue;
+            };
+            let (consumption, changed) = self.counters.entry(label.clone()).or_default().update(today, label, raw, value);
+            self.changed |= changed;
+            let suffixes = COUNTERS.iter().flat_map(|(_, current, previous)| [current, previous]);
+            for ((suffix, value), total) in suffixes.zip(consumption).zip(&mut totals) {
+                if energy { *total = total.zip(value).map(|(a, b)| a + b); }
+                if let Some(value) = value {
+                    counters.push(Metric { id: format!("{}_{}", label, suffix), value: Value::Integer(value) });
+                }
+            }
+            consumptions.insert(label.clone(), consumption);
+        }
+        if complete {
+            let returned = returned.and_then(|(label, _)| consumptions.get(label)).copied();
+            let suffixes = COUNTERS.iter().flat_map(|(_, current, previous)| [current, previous]);
+            for (i, (suffix, total)) in suffixes.zip(totals).enumerate() {
+                let Some(total) = total else { continue };
+                self.metrics.push(Metric { id: format!("TOTAL_{}", suffix), value: Value::Integer(total) });
+                // Drawn minus returned
+                if let Some(returned) = returned.and_then(|r| r[i]) {
+                    self.metrics.push(Metric { id: format!("NET_{}", suffix), value: Value::Integer(total - returned) });
+                }
+            }
+        }
+        // Produced minus returned, never negative as the two indexes are not read at the same time
+        if let (Some(produced), Some(returned)) = (consumptions.get("PRODUCTION"), returned.and_then(|(label, _)| consumptions.get(label))) {
+            let suffixes = COUNTERS.iter().flat_map(|(_, current, previous)| [current, previous]);
+            for ((suffix, produced), returned) in suffixes.zip(produced).zip(returned) {
+                if let (Some(produced), Some(returned)) = (produced, returned) {
+                    self.metrics.push(Metric { id: format!("SELF_{}", suffix), value: Value::Integer((produced - returned).max(0)) });
+                }
+            }
+        }
+        if let Some(tariff) = self.tariff.as_ref().filter(|t| t.prices.keys().any(|l| consumptions.contains_key(l))) {
+            if let Some(rate) = mode.active_index().and_then(|index| tariff.prices.get(&index)) {
+                self.metrics.push(Metric { id: "rate".to_string(), value: Value::Float(*rate) });
+            }
+            // The subscription is spread over the days of the month, the month so far paying for its days
+            let month = Period::Month.start(today);
+            let daily = tariff.subscription / (Period::Month.next(month) - month) as f64;
+            for (id, slot, subscription) in [("cost_today", TODAY, daily), ("cost_this_month", THIS_MONTH, daily * (today - month + 1) as f64)] {
+                let mut cost = subscription;
+                for (label, price) in &tariff.prices {
+                    let Some(wh) = consumptions.get(label).and_then(|c| c[slot]) else { continue };
+                    let sign = if mode.return_index() == Some(label.as_str()) { -1.0 } else { 1.0 };
+                    cost += sign * wh as f64 / 1000.0 * price;
+                }
+                self.metrics.push(Metric { id: id.to_string(), value: Value::Float((cost * 100.0).round() / 100.0) });
+            }
+        }
+        self.metrics.extend(counters);
+    }
+
+    /// Metrics of the last update
+    pub fn metrics(&self) -> Vec<Metric> {
+        self.metrics.clone()
+    }
+}
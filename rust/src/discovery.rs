@@ -0,0 +1,207 @@
+use tic2mqtt::derived::metric_info;
+use tic2mqtt::tic::TicMode;
+use tic2mqtt::utils::{json_escape, sanitize_label};
+
+use crate::topics::{MQTT_ID_BASE, Topics};
+
+/// User overrides of the discovery metadata of a label, an empty string removes the field
+#[derive(Clone, Debug, Default)]
+pub struct LabelOverride {
+    pub name: Option<String>,
+    pub device_class: Option<String>,
+    pub state_class: Option<String>,
+    pub unit: Option<String>,
+    /// Some(false) never announces the label
+    pub enabled: Option<bool>,
+}
+
+pub fn get_object_id(meter: &str, label: &str) -> String {
+    let safe_label = sanitize_label(label);
+    format!("{}_{}_{}", MQTT_ID_BASE, meter, safe_label)
+}
+
+/// Config topics of the single meter releases, "<discovery_prefix>/sensor/<meter address>/tic2mqtt_<label>/config",
+/// replaced since by the topics of `get_object_id`
+pub fn legacy_config_topics(mode: &dyn TicMode, topics: &Topics) -> Vec<String> {
+    let address = mode.get_meter_id();
+    if address.is_empty() { return Vec::new(); }
+    mode.labels().iter()
+        .map(|label| topics.discovery("sensor", &address, &format!("{}_{}", MQTT_ID_BASE, sanitize_label(label))))
+        .collect()
+}
+
+/// One Home Assistant entity announced for a label
+struct Entity {
+    component: &'static str,
+    object_id: String,
+    name: String,
+    state_topic: String,
+    value_template: Option<String>,
+    /// Event types of an event entity
+    event_types: &'static [&'static str],
+    device_class: Option<String>,
+    state_class: Option<String>,
+    unit: Option<String>,
+    entity_category: Option<&'static str>,
+}
+
+/// Entities of a label: a sensor of a derived metric or of a label, a problem binary sensor for warnings (ADPS, ADIRx), and
+/// binary sensors for flags (PPOT phases, STGE alerts and producer flags). Phase labels are named "TIC phase N ...".
+fn entities(mode: &dyn TicMode, label: &str, meter: &str, topics: &Topics, overrides: Option<&LabelOverride>) -> Vec<Entity> {
+    let safe_label = sanitize_label(label);
+    let state_topic = topics.state(meter);
+    let object_id = get_object_id(meter, label);
+    // Overrides take precedence, an empty string drops the field
+    let pick = |user: Option<&String>, default: Option<&'static str>| -> Option<String> {
+        user.cloned().or_else(|| default.map(String::from)).filter(|v| !v.is_empty())
+    };
+    let user_name = overrides.and_then(|o| o.name.clone());
+    if let Some(metric) = metric_info(label) {
+        return vec![Entity {
+            component: "sensor",
+            object_id,
+            name: user_name.unwrap_or_else(|| format!("TIC {}", metric.name)),
+            state_topic,
+            value_template: Some(format!("{{{{ value_json['{}'] | default({{}}) | attr('value') | default('') }}}}", safe_label)),
+            event_types: &[],
+            device_class: pick(overrides.and_then(|o| o.device_class.as_ref()), metric.device_class),
+            state_class: pick(overrides.and_then(|o| o.state_class.as_ref()), metric.state_class),
+            unit: pick(overrides.and_then(|o| o.unit.as_ref()), metric.unit),
+            entity_category: None,
+        }];
+    }
+    let name = user_name.clone().unwrap_or_else(|| match mode.phase(label) {
+        Some(phase) => format!("TIC phase {} {}", phase, safe_label),
+        None => format!("TIC {}", safe_label),
+    });
+    let device_class = |default| pick(overrides.and_then(|o| o.device_class.as_ref()), default);
+
+    if label == "PPOT" {
+        return (1..=3)
+            .map(|phase| Entity {
+                component: "binary_sensor",
+                object_id: format!("{}_{}", object_id, phase),
+                name: user_name.as_ref().map_or_else(|| format!("TIC phase {} {}", phase, safe_label), |n| format!("{} {}", n, phase)),
+                state_topic: state_topic.clone(),
+                value_template: Some(flag_template(&safe_label, &format!("phase{}", phase))),
+                event_types: &[],
+                device_class: device_class(Some("power")),
+                state_class: None,
+                unit: None,
+                entity_category: None,
+            })
+            .collect();
+    }
+    if mode.is_event(label) {
+        return vec![Entity {
+            component: "binary_sensor",
+            object_id,
+            name,
+            state_topic,
+            value_template: Some(format!("{{{{ 'ON' if '{}' in value_json else 'OFF' }}}}", safe_label)),
+            event_types: &[],
+            device_class: device_class(Some("problem")),
+            state_class: None,
+            unit: None,
+            entity_category: None,
+        }];
+    }
+    let mut entities = vec![Entity {
+        component: "sensor",
+        object_id: object_id.clone(),
+        name: name.clone(),
+        state_topic: state_topic.clone(),
+        value_template: Some(format!("{{{{ value_json['{}'] | default({{}}) | attr('raw') | default('') }}}}", safe_label)),
+        event_types: &[],
+        device_class: device_class(mode.get_ha_device_class(label)),
+        state_class: pick(overrides.and_then(|o| o.state_class.as_ref()), mode.get_ha_state_class(label)),
+        unit: pick(overrides.and_then(|o| o.unit.as_ref()), mode.get_ha_unit(label)),
+        entity_category: mode.get_ha_entity_category(label),
+    }];
+    if label == "STGE" {
+        // Alerts, then the producer flags
+        let flags = [("overvoltage", Some("problem"), None), ("overload", Some("problem"), None), ("producer", None, Some("diagnostic")), ("injecting", Some("power"), None)];
+        entities.extend(flags.into_iter().map(|(flag, device_class, entity_category)| Entity {
+            component: "binary_sensor",
+            object_id: format!("{}_{}", object_id, flag),
+            name: format!("{} {}", name, flag),
+            state_topic: state_topic.clone(),
+            value_template: Some(flag_template(&safe_label, flag)),
+            event_types: &[],
+            device_class: device_class.map(String::from),
+            state_class: None,
+            unit: None,
+            entity_category,
+        }));
+    }
+    entities
+}
+
+/// Value template of a binary sensor on a decoded flag of a label
+fn flag_template(label: &str, field: &str) -> String {
+    format!("{{{{ 'ON' if value_json['{}'] | default({{}}) | attr('{}') else 'OFF' }}}}", label, field)
+}
+
+/// Discovery config payload of an entity of the named meter
+fn config_payload(entity: Entity, mode: &dyn TicMode, meter: &str, topics: &Topics) -> String {
+    let mut payload = format!(
+        "{{\"name\":\"{}\",\"state_topic\":\"{}\",\"unique_id\":\"{}\"",
+        json_escape(&entity.name), entity.state_topic, entity.object_id
+    );
+    if let Some(template) = &entity.value_template {
+        payload.push_str(&format!(",\"value_template\":\"{}\"", template));
+    }
+    if !entity.event_types.is_empty() {
+        let types: Vec<String> = entity.event_types.iter().map(|t| format!("\"{}\"", t)).collect();
+        payload.push_str(&format!(",\"event_types\":[{}]", types.join(",")));
+    }
+    // Available while both the bridge and the meter are online
+    payload.push_str(&format!(
+        ",\"availability\":[{{\"topic\":\"{}\"}},{{\"topic\":\"{}\"}}],\"availability_mode\":\"all\"",
+        topics.bridge_availability(), topics.availability(meter)
+    ));
+    // Device block, one device per meter
+    payload.push_str(&format!(
+        ",\"device\":{{\"identifiers\":[\"{}_{}\"],\"manufacturer\":\"Enedis\",\"model\":\"TIC {}\",\"name\":\"Télé-information client {}\"}}",
+        MQTT_ID_BASE, meter, mode.get_mode_name(), meter
+    ));
+    if let Some(dc) = entity.device_class {
+        payload.push_str(&format!(",\"device_class\":\"{}\"", json_escape(&dc)));
+    }
+    if let Some(sc) = entity.state_class {
+        payload.push_str(&format!(",\"state_class\":\"{}\"", json_escape(&sc)));
+    }
+    if let Some(u) = entity.unit {
+        payload.push_str(&format!(",\"unit_of_measurement\":\"{}\"", json_escape(&u)));
+    }
+    if let Some(category) = entity.entity_category {
+        payload.push_str(&format!(",\"entity_category\":\"{}\"", category));
+    }
+    payload.push('}');
+    payload
+}
+
+/// Discovery configs (topic, payload) of the entities of a label of the named meter
+pub fn get_discovery_messages(mode: &dyn TicMode, label: &str, meter: &str, topics: &Topics, overrides: Option<&LabelOverride>) -> Vec<(String, String)> {
+    entities(mode, label, meter, topics, overrides)
+        .into_iter()
+        .map(|entity| (topics.discovery(entity.component, meter, &entity.object_id), config_payload(entity, mode, meter, topics)))
+        .collect()
+}
+
+/// Discovery config (topic, payload) of the alert events of the named meter
+pub fn get_event_discovery_message(mode: &dyn TicMode, meter: &str, topics: &Topics) -> (String, String) {
+    let entity = Entity {
+        component: "event",
+        object_id: format!("{}_{}_alert", MQTT_ID_BASE, meter),
+        name: "TIC alert".to_string(),
+        state_topic: topics.event(meter),
+        value_template: None,
+        event_types: &["alert_start", "alert_end"],
+        device_class: None,
+        state_class: None,
+        unit: None,
+        entity_category: None,
+    };
+    (topics.discovery(entity.component, meter, &entity.object_id), config_payload(entity, mode, meter, topics))
+}
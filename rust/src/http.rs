@@ -0,0 +1,218 @@
+//! Minimal HTTP/1.1 server on std, for the metrics and API endpoints: one request per connection, one thread per connection,
+//! `MAX_CONNECTIONS` at most.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Connections served at once, the next ones get a 503 until one ends
+const MAX_CONNECTIONS: usize = 32;
+/// Connections refused at once after reading their request, the next ones are refused without reading it
+const MAX_REFUSERS: usize = 4;
+/// Time allowed to a refused client to send its request
+const REFUSE_DEADLINE: Duration = Duration::from_secs(1);
+
+/// Request line of a request, the headers are ignored
+pub struct Request {
+    pub method: String,
+    /// Path without the query string
+    pub path: String,
+    /// Query string, without the '?'
+    pub query: String,
+}
+
+/// Decode %XX escapes, '+' is kept as is
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => { out.push(b); i += 3; }
+            (b, _) => { out.push(b); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl Request {
+    /// Decoded value of a query parameter
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.query.split('&')
+            .filter_map(|p| p.split_once('='))
+            .find(|(key, _)| percent_decode(key) == name)
+            .map(|(_, value)| percent_decode(value))
+    }
+}
+
+/// Writer of a streamed body, until the client goes away or the stream ends
+pub type Stream = Box<dyn FnOnce(&mut TcpStream) -> io::Result<()> + Send>;
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+    /// Body written after the headers instead of `body`, without a length, e.g. server-sent events
+    pub stream: Option<Stream>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<String>) -> Self {
+        Response { status, content_type, body: body.into(), stream: None }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Response::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    pub fn json(status: u16, body: impl Into<String>) -> Self {
+        Response::new(status, "application/json", body)
+    }
+
+    /// Stream of server-sent events, readable from any origin
+    pub fn events(stream: impl FnOnce(&mut TcpStream) -> io::Result<()> + Send + 'static) -> Self {
+        Response { stream: Some(Box::new(stream)), ..Response::new(200, "text/event-stream", "") }
+    }
+
+    pub fn not_found() -> Self {
+        Response::text(404, "not found\n")
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Slot of a connection being served, freed when dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    /// Slot among `max` ones, none if all are taken
+    fn take(count: &Arc<AtomicUsize>, max: usize) -> Option<Slot> {
+        if count.fetch_add(1, Ordering::Relaxed) >= max {
+            count.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(Slot(count.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Answer a connection beyond `MAX_CONNECTIONS` with a 503, once its request is read until `deadline`: a request
+/// left unread makes the client see a reset instead of the answer
+fn refuse(mut stream: TcpStream, deadline: Instant) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() { break; }
+        stream.set_read_timeout(Some(left))?;
+        match stream.read(&mut buf) {
+            Ok(n) if n > 0 => request.extend_from_slice(&buf[..n]),
+            _ => break,
+        }
+    }
+    stream.set_write_timeout(Some(Duration::from_millis(100)))?;
+    let body = "too many connections\n";
+    write!(
+        stream,
+        "HTTP/1.1 503 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nRetry-After: 5\r\nConnection: close\r\n\r\n{}",
+        reason(503), body.len(), body
+    )?;
+    stream.flush()
+}
+
+/// Listen on `addr`, e.g. "0.0.0.0:9100", and answer the requests with `handler` from a background thread
+pub fn spawn(addr: &str, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    log::info!("[HTTP] listening on {}", listener.local_addr()?);
+    let handler = Arc::new(handler);
+    let (connections, refusers) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let Some(slot) = Slot::take(&connections, MAX_CONNECTIONS) else {
+                log::debug!("[HTTP] too many connections, refusing {}", stream.peer_addr().map(|a| a.to_string()).unwrap_or_default());
+                match Slot::take(&refusers, MAX_REFUSERS) {
+                    Some(slot) => {
+                        std::thread::spawn(move || {
+                            let _ = refuse(stream, Instant::now() + REFUSE_DEADLINE);
+                            drop(slot);
+                        });
+                    }
+                    // From the listening thread, without waiting for the request
+                    None => { let _ = refuse(stream, Instant::now()); }
+                }
+                continue;
+            };
+            let handler = handler.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = serve(stream, handler.as_ref()) {
+                    log::debug!("[HTTP] connection error: {}", e);
+                }
+                drop(slot);
+            });
+        }
+    });
+    Ok(())
+}
+
+fn serve(mut stream: TcpStream, handler: &dyn Fn(&Request) -> Response) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    let mut parts = line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => {
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let request = Request { method: method.to_string(), path: path.to_string(), query: query.to_string() };
+            match request.method.as_str() {
+                "GET" | "HEAD" => handler(&request),
+                _ => Response::text(405, "method not allowed\n"),
+            }
+        }
+        _ => Response::text(400, "bad request\n"),
+    };
+    if let Some(body) = response.stream {
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+            response.status, reason(response.status), response.content_type
+        )?;
+        stream.flush()?;
+        if line.starts_with("HEAD ") { return Ok(()); }
+        // A client not reading is dropped
+        stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+        return body(&mut stream);
+    }
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, reason(response.status), response.content_type, response.body.len()
+    )?;
+    if !line.starts_with("HEAD ") {
+        stream.write_all(response.body.as_bytes())?;
+    }
+    stream.flush()
+}
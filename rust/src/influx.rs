@@ -0,0 +1,160 @@
+//! InfluxDB output: each frame as a point in line protocol, sent to a UDP or HTTP endpoint, or appended to a file.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, UNIX_EPOCH};
+
+use tic2mqtt::tic::Value;
+use tic2mqtt::utils::parse_timestamp;
+
+use crate::meter::{FrameUpdate, Update};
+use crate::output::{OutputSink, Updates};
+
+/// Time allowed to an HTTP endpoint to accept, read and answer a write, so that one hung does not hold the output
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings of the InfluxDB output
+pub struct InfluxConfig {
+    /// Target as configured, for display
+    pub url: String,
+    pub target: InfluxTarget,
+    /// API token of an HTTP target, none if empty
+    pub token: String,
+}
+
+/// Where the points are written
+#[derive(Clone, Debug, PartialEq)]
+pub enum InfluxTarget {
+    /// host:port of a UDP listener
+    Udp(String),
+    /// host:port, and path and query of the write endpoint, e.g. "/api/v2/write?org=home&bucket=tic"
+    Http { server: String, path: String },
+    File(String),
+}
+
+impl InfluxTarget {
+    /// Parse udp://host:port, http://host:port/path?query or file:///path
+    pub fn parse(url: &str) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        match scheme {
+            "udp" if rest.contains(':') => Some(InfluxTarget::Udp(rest.to_string())),
+            "http" => {
+                let (server, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+                if server.is_empty() { return None; }
+                let server = if server.contains(':') { server.to_string() } else { format!("{}:80", server) };
+                Some(InfluxTarget::Http { server, path: path.to_string() })
+            }
+            "file" if !rest.is_empty() => Some(InfluxTarget::File(rest.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Escape commas, equal signs and spaces of a measurement, tag or field key
+fn escape_key(key: &str) -> String {
+    key.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+/// Line protocol of a frame: the measurement is the meter name, tagged with the mode, the tariff option and the
+/// tariff period, with a field per label and derived metric, at the meter date if any, else the reception time
+pub fn line(frame: &FrameUpdate) -> Option<String> {
+    let text = |labels: [&str; 2]| {
+        frame.values.iter()
+            .find(|(label, _)| labels.contains(&label.as_str()))
+            .map(|(_, value)| value.to_string().trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let mut line = escape_key(&frame.meter);
+    line.push_str(&format!(",mode={}", frame.mode));
+    for (tag, labels) in [("tariff", ["OPTARIF", "NGTF"]), ("period", ["PTEC", "LTARF"])] {
+        if let Some(value) = text(labels) {
+            line.push_str(&format!(",{}={}", tag, escape_key(&value)));
+        }
+    }
+    let values = frame.values.iter().map(|(label, value)| (label.as_str(), value));
+    let metrics = frame.metrics.iter().map(|m| (m.id.as_str(), &m.value));
+    let fields: Vec<String> = values.chain(metrics)
+        .filter_map(|(key, value)| {
+            let value = match value {
+                Value::Integer(v) => format!("{}i", v),
+                Value::Float(v) if v.is_finite() => v.to_string(),
+                Value::Float(_) => return None,
+                Value::Bool(b) => b.to_string(),
+                Value::Text(s) if s.trim().is_empty() => return None,
+                Value::Text(s) => format!("\"{}\"", s.trim().replace('\\', "\\\\").replace('"', "\\\"")),
+            };
+            Some(format!("{}={}", escape_key(key), value))
+        })
+        .collect();
+    if fields.is_empty() { return None; }
+    let time = frame.date.as_deref().and_then(parse_timestamp).unwrap_or(frame.time);
+    let nanos = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    Some(format!("{} {} {}", line, fields.join(","), nanos))
+}
+
+pub struct InfluxSink {
+    target: InfluxTarget,
+    /// API token of the HTTP endpoint, none if empty
+    token: String,
+    socket: Option<UdpSocket>,
+}
+
+impl InfluxSink {
+    pub fn new(target: InfluxTarget, token: String) -> Self {
+        InfluxSink { target, token, socket: None }
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        match &self.target {
+            InfluxTarget::Udp(addr) => {
+                if self.socket.is_none() {
+                    self.socket = Some(UdpSocket::bind("0.0.0.0:0")?);
+                }
+                self.socket.as_ref().map_or(Ok(0), |s| s.send_to(line.as_bytes(), addr))?;
+                Ok(())
+            }
+            InfluxTarget::Http { server, path } => {
+                let addr = server.to_socket_addrs()?.next().ok_or_else(|| std::io::Error::other("no address"))?;
+                let mut stream = TcpStream::connect_timeout(&addr, HTTP_TIMEOUT)?;
+                stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+                stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+                let mut request = format!(
+                    "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    path, server, line.len() + 1
+                );
+                if !self.token.is_empty() {
+                    request.push_str(&format!("Authorization: Token {}\r\n", self.token));
+                }
+                stream.write_all(format!("{}\r\n{}\n", request, line).as_bytes())?;
+                let mut response = String::new();
+                stream.read_to_string(&mut response)?;
+                let status = response.lines().next().unwrap_or("");
+                if status.split_whitespace().nth(1).is_some_and(|code| code.starts_with('2')) {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::other(format!("write rejected: {}", status)))
+                }
+            }
+            InfluxTarget::File(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", line)
+            }
+        }
+    }
+}
+
+impl OutputSink for InfluxSink {
+    fn name(&self) -> &'static str { "Influx" }
+
+    /// Write the frames, alert events are not written
+    fn run(&mut self, updates: Updates) {
+        for (_, update) in updates {
+            let Update::Frame(frame) = update else { continue };
+            let Some(line) = line(&frame) else { continue };
+            if let Err(e) = self.write(&line) {
+                log::warn!("[Influx] {:?}: {}", self.target, e);
+            }
+        }
+    }
+}
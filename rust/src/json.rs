@@ -0,0 +1,85 @@
+//! JSON lines output: one object per frame, written to stdout or to a rotating file.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+
+use tic2mqtt::utils::{format_timestamp, json_escape};
+
+use crate::meter::{FrameUpdate, Update};
+use crate::output::{self, OutputSink, Updates};
+
+/// Size of the output file before it is rotated
+pub const JSON_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Rotated output files kept, as <path>.1 (newest) to <path>.<JSON_KEEP>
+pub const JSON_KEEP: usize = 5;
+
+/// JSON object of a frame, on one line:
+/// {"meter": ..., "mode": ..., "timestamp": ..., "date": ..., "labels": {"LABEL": value, ...}, "metrics": {"id": value, ...}}
+/// with the time of reception as timestamp, and the meter date (DATE) if any
+pub fn line(frame: &FrameUpdate) -> String {
+    let mut line = format!(
+        "{{\"meter\": \"{}\", \"mode\": \"{}\", \"timestamp\": \"{}\"",
+        json_escape(&frame.meter),
+        frame.mode,
+        format_timestamp(frame.time)
+    );
+    if let Some(date) = &frame.date {
+        line.push_str(&format!(", \"date\": \"{}\"", json_escape(date)));
+    }
+    let labels: Vec<String> = frame.values.iter().map(|(label, value)| format!("\"{}\": {}", json_escape(label), value.to_json())).collect();
+    let metrics: Vec<String> = frame.metrics.iter().map(|m| format!("\"{}\": {}", json_escape(&m.id), m.value.to_json())).collect();
+    line.push_str(&format!(", \"labels\": {{{}}}, \"metrics\": {{{}}}}}", labels.join(", "), metrics.join(", ")));
+    line
+}
+
+/// Writes the frames to stdout if the path is "-", to a file rotated at `JSON_MAX_SIZE` otherwise
+pub struct JsonSink {
+    path: String,
+    file: Option<File>,
+    size: u64,
+}
+
+impl JsonSink {
+    pub fn new(path: String) -> Self {
+        JsonSink { path, file: None, size: 0 }
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.path == "-" {
+            let mut stdout = io::stdout().lock();
+            writeln!(stdout, "{}", line)?;
+            return stdout.flush();
+        }
+        if self.file.is_some() && self.size >= JSON_MAX_SIZE {
+            self.file = None;
+            output::rotate(&self.path, JSON_KEEP)?;
+            log::info!("[JSON] {}: output file rotated", self.path);
+        }
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line)?;
+            self.size += line.len() as u64 + 1;
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for JsonSink {
+    fn name(&self) -> &'static str { "JSON" }
+
+    /// Write the frames, alert events are not written
+    fn run(&mut self, updates: Updates) {
+        for (_, update) in updates {
+            let Update::Frame(frame) = update else { continue };
+            if let Err(e) = self.write(&line(&frame)) {
+                log::warn!("[JSON] {}: {}", self.path, e);
+                // Reopened on the next frame
+                self.file = None;
+            }
+        }
+    }
+}
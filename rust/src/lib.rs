@@ -0,0 +1,25 @@
+//! Decoding of the Enedis Linky / CBE "télé-information client" (TIC) serial output.
+//!
+//! The [`tic::frame`] module assembles the serial lines into checked frames, and the
+//! [`tic::TicMode`] implementations keep the last value of each label of a meter,
+//! in historique or standard mode. The [`derived`] module computes metrics over successive
+//! frames, such as the active power.
+//!
+//! ```
+//! use tic2mqtt::tic::frame::FrameDecoder;
+//! use tic2mqtt::tic::{TicModeEnum, TicModeHandle};
+//!
+//! let meter = TicModeHandle::new(TicModeEnum::Historique);
+//! let mut decoder = FrameDecoder::new();
+//! for line in ["\x02", "ADCO 012345678901 E\r", "PAPP 00420 '\r\x03"] {
+//!     for frame in decoder.push_line(line) {
+//!         meter.handle_frame(&frame.unwrap());
+//!     }
+//! }
+//! assert_eq!(meter.get_meter_id(), "012345678901");
+//! assert_eq!(meter.decoded_value("PAPP").and_then(|v| v.as_i64()), Some(420));
+//! ```
+
+pub mod derived;
+pub mod tic;
+pub mod utils;
@@ -1,159 +1,213 @@
 use std::sync::mpsc;
 use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 use std::thread;
-
+use std::time::Instant;
+
+use tic2mqtt::derived::{Derived, ProductionIndex};
+use tic2mqtt::tic::TicModeHandle;
+
+mod api;
+mod capture;
+mod config;
+mod csv;
+mod discovery;
+mod http;
+mod influx;
+mod json;
+mod logger;
+mod meter;
 mod mqtt;
+mod output;
+mod prometheus;
 mod serial;
-mod tic;
-mod utils;
-
+mod sqlite;
+mod state;
+mod stats;
+mod stream;
+mod topics;
+
+use api::Api;
+use config::{Config, Input, USAGE};
+use csv::CsvSink;
+use influx::InfluxSink;
+use json::JsonSink;
 use mqtt::MqttPublisher;
+use meter::Meter;
+use output::{OutputKind, OutputSink};
 use serial::SerialReader;
-use tic::{TicModeEnum, TicModeHandle};
-
-struct Opt {
-    serial: Option<String>,
-    mqtt_server: Option<String>,
-    mqtt_user: Option<String>,
-    mqtt_pass: Option<String>,
-    mqtt_client_id: Option<String>,
-    mode: Option<String>,
-}
+use sqlite::SqliteSink;
+use state::StateFile;
+use stats::MqttStats;
+use stream::{StreamSink, Subscribers};
 
-fn parse_args() -> Opt {
-    let mut opt = Opt {
-        serial: None,
-        mqtt_server: None,
-        mqtt_user: None,
-        mqtt_pass: None,
-        mqtt_client_id: None,
-        mode: None,
-    };
-    let args: Vec<String> = std::env::args().collect();
-    let mut i = 1;
-    while i < args.len() {
-        match args[i].as_str() {
-            "--serial" => { i += 1; if i < args.len() { opt.serial = Some(args[i].clone()); } },
-            "--mqtt_server" => { i += 1; if i < args.len() { opt.mqtt_server = Some(args[i].clone()); } },
-            "--mqtt_user" => { i += 1; if i < args.len() { opt.mqtt_user = Some(args[i].clone()); } },
-            "--mqtt_pass" => { i += 1; if i < args.len() { opt.mqtt_pass = Some(args[i].clone()); } },
-            "--mqtt_client_id" => { i += 1; if i < args.len() { opt.mqtt_client_id = Some(args[i].clone()); } },
-            "--mode" => { i += 1; if i < args.len() { opt.mode = Some(args[i].clone()); } },
-            _ => {},
+fn main() {
+    let opt = match config::parse_args(std::env::args().skip(1)) {
+        Ok(opt) => opt,
+        Err(e) => {
+            eprintln!("error: {}\n\nTry 'tic2mqtt --help' for more information.", e.message);
+            // Docker reserves the exit code 2 of health checks
+            std::process::exit(if e.healthcheck { 1 } else { 2 });
         }
-        i += 1;
+    };
+    if opt.help {
+        println!("tic2mqtt {}\n\n{}", env!("CARGO_PKG_VERSION"), USAGE);
+        return;
     }
-    opt
-}
-
-fn get_tic_mode(opt_mode: Option<String>) -> TicModeEnum {
-    let mode_str = opt_mode
-        .or_else(|| std::env::var("TIC_MODE").ok())
-        .unwrap_or_else(|| "standard".into())
-        .to_lowercase();
-
-    match mode_str.as_str() {
-        "historique" => TicModeEnum::Historique,
-        _ => TicModeEnum::Standard,
+    if opt.version {
+        println!("tic2mqtt {}", env!("CARGO_PKG_VERSION"));
+        return;
     }
-}
-
-fn get_env_or(opt: Option<String>, var: &str, default: &str) -> String {
-    opt.or_else(|| std::env::var(var).ok())
-        .unwrap_or_else(|| default.into())
-}
-
-fn main() {
-    let opt = parse_args();
+    // Exit 1 on any failure, as expected by the container health checks
+    if opt.healthcheck {
+        let listen = config::healthcheck_listen(opt).unwrap_or_else(|e| {
+            println!("error: {}", e);
+            std::process::exit(1);
+        });
+        match api::check(&listen) {
+            Ok(body) => print!("{}", body),
+            Err(e) => {
+                println!("{}", e.trim_end());
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let print_config = opt.print_config;
 
     // Get configuration from CLI args or environment
-    let mqtt_server = get_env_or(opt.mqtt_server, "MQTT_SERVER", "tcp://localhost:1883");
-    let mqtt_user = get_env_or(opt.mqtt_user, "MQTT_USER", "");
-    let mqtt_pass = get_env_or(opt.mqtt_pass, "MQTT_PASS", "");
-    let mqtt_client_id = get_env_or(opt.mqtt_client_id, "MQTT_CLIENT_ID", "tic2mqtt_client");
-    let serial_port = get_env_or(opt.serial, "SERIAL_PORT", "/dev/ttyUSB0");
+    let config = match Config::from_opt(opt) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if print_config {
+        print!("{}", config.to_redacted_string());
+        return;
+    }
+    logger::init(config.log_level);
+    for warning in &config.warnings {
+        log::warn!("{}", warning);
+    }
 
-    // Channels
-    let (line_tx, line_rx) = mpsc::channel::<String>();
-    let (publish_tx, publish_rx) = mpsc::channel::<(String, String)>();
     let shutdown = Arc::new(AtomicBool::new(false));
 
-    // Initialize TIC mode
-    let tic_mode = std::sync::Arc::new(TicModeHandle::new(get_tic_mode(opt.mode)));
-    let baudrate = tic_mode.baudrate();
-
-    // Start serial reader with configured port
-    let serial_shutdown = shutdown.clone();
-    let serial_handle = {
-        let _tic_mode = tic_mode.clone();
-        thread::spawn(move || {
-            let mut serial = SerialReader::new(Some(serial_port), line_tx).with_baud(baudrate);
-            serial.run(&serial_shutdown);
+    // Initialize TIC modes, one per meter
+    let overrides = Arc::new(config.labels);
+    let production = config.derived.production_topic.as_ref().map(|_| Arc::new(ProductionIndex::default()));
+    let meters: Vec<Meter> = config.meters.iter()
+        .map(|m| {
+            let mut derived = Derived::new().with_power_window(config.derived.power_window);
+            if let Some(tariff) = m.tariff.as_ref().or(config.derived.tariff.as_ref()) {
+                derived = derived.with_tariff(tariff.clone());
+            }
+            if let Some(production) = &production {
+                derived = derived.with_production(production.clone());
+            }
+            Meter::new(TicModeHandle::new(m.mode.clone()), m.id.clone(), overrides.clone(), derived)
         })
-    };
+        .collect();
+
+    // Consumption counters saved across restarts
+    let state_file = config.derived.state_file.as_deref().map(|path| Arc::new(StateFile::load(path)));
+
+    // Start the outputs enabled, each on its own thread
+    let mut mqtt_stats = None;
+    let (mut mqtt_config, mut influx, mut json_output, mut csv, mut sqlite) =
+        (Some(config.mqtt), config.influx, config.json_output, config.csv, config.sqlite);
+    let mut sinks: Vec<Box<dyn OutputSink>> = Vec::new();
+    let mut history = None;
+    for kind in &config.outputs {
+        // Each kind is listed once
+        match kind {
+            OutputKind::Mqtt => if let Some(mqtt_config) = mqtt_config.take() {
+                let stats = Arc::new(MqttStats::default());
+                mqtt_stats = Some(stats.clone());
+                let mut publisher = MqttPublisher::new(mqtt_config, meters.clone()).with_stats(stats);
+                if let (Some(topic), Some(production)) = (config.derived.production_topic.clone(), production.clone()) {
+                    publisher = publisher.with_production(topic, production);
+                }
+                sinks.push(Box::new(publisher));
+            }
+            OutputKind::Influx => if let Some(influx) = influx.take() {
+                sinks.push(Box::new(InfluxSink::new(influx.target, influx.token)));
+            }
+            OutputKind::Json => if let Some(path) = json_output.take() {
+                sinks.push(Box::new(JsonSink::new(path)));
+            }
+            OutputKind::Csv => if let Some(csv) = csv.take() {
+                sinks.push(Box::new(CsvSink::new(csv.dir, &meters).with_interval(csv.interval)));
+            }
+            OutputKind::Sqlite => if let Some(sqlite) = sqlite.take() {
+                history = Some(sqlite.path.clone());
+                sinks.push(Box::new(SqliteSink::new(sqlite)));
+            }
+        }
+    }
+    // Live stream of the HTTP server
+    let subscribers = Subscribers::default();
+    if config.http_listen.is_some() {
+        sinks.push(Box::new(StreamSink::new(subscribers.clone())));
+    }
+    let (outputs, output_handles) = output::spawn(sinks);
+
+    // Prometheus metrics of the meters and of the outputs
+    if let Some(listen) = &config.metrics_listen
+        && let Err(e) = prometheus::spawn(listen, meters.clone(), mqtt_stats.clone(), outputs.stats())
+    {
+        log::error!("cannot listen on {}: {}", listen, e);
+        std::process::exit(1);
+    }
 
-    // Start MQTT publisher with full configuration
-    let mqtt_config = mqtt::MqttConfig {
-        server: mqtt_server,
-        client_id: mqtt_client_id,
-        username: mqtt_user,
-        password: mqtt_pass,
-    };
-    let mqtt_handle = {
-        let tic_mode = tic_mode.clone();
-        thread::spawn(move || {
-            let mut mqtt = MqttPublisher::new(mqtt_config, publish_rx, (*tic_mode).clone());
-            mqtt.run();
-        })
-    };
+    // REST API of the current readings
+    if let Some(listen) = &config.http_listen
+        && let Err(e) = api::spawn(listen, Api { meters: meters.clone(), mqtt: mqtt_stats.clone(), outputs: outputs.stats(), subscribers, started: Instant::now(), history })
+    {
+        log::error!("cannot listen on {}: {}", listen, e);
+        std::process::exit(1);
+    }
+
+    // Start serial reader and decoder of each meter
+    let mut handles = Vec::new();
+    for (index, (meter_config, meter)) in config.meters.into_iter().zip(meters.iter().cloned()).enumerate() {
+        let (line_tx, line_rx) = mpsc::channel::<String>();
+        let baudrate = meter_config.baudrate.unwrap_or_else(|| meter.mode.baudrate());
+        let source = match &meter_config.input {
+            Input::Serial(port) => port.clone(),
+            Input::Stdin => "stdin".to_string(),
+            Input::Replay { path, .. } => format!("replay of {}", path),
+        };
+        log::info!("meter {}: {} at {} baud", index, source, baudrate);
+        let serial_shutdown = shutdown.clone();
+        let stats = meter.stats.clone();
+        handles.push(thread::spawn(move || {
+            let mut serial = SerialReader::new(meter_config.input, line_tx)
+                .with_baud(baudrate)
+                .with_record(meter_config.record)
+                .with_stats(stats);
+            serial.run(&serial_shutdown);
+        }));
+        let meter_shutdown = shutdown.clone();
+        let outputs = outputs.clone();
+        let state_file = state_file.clone();
+        handles.push(thread::spawn(move || {
+            meter::run(index, meter, line_rx, outputs, state_file, &meter_shutdown);
+        }));
+    }
+    drop(outputs);
 
-    // listen for ctrl-c and signal shutdown
+    // listen for ctrl-c (and SIGTERM) and signal shutdown
     let shutdown_ctrlc = shutdown.clone();
     ctrlc::set_handler(move || {
         shutdown_ctrlc.store(true, Ordering::SeqCst);
     }).expect("Error setting Ctrl-C handler");
 
-    // Main loop: parse lines into label/value and forward
-    while let Ok(line) = line_rx.recv() {
-        let line = line.trim().to_string();
-        if line.is_empty() {
-            continue;
-        }
-        if let Some((label, value)) = utils::parse_label_value(&line) {
-            let is_frame_start = label == "ADCO" || label == "ADSC";
-            if is_frame_start {
-                // On frame start, publish previous frame if any
-                let meter_id = tic_mode.get_meter_id();
-                if !meter_id.is_empty() {
-                    let label_values = tic_mode.get_label_values();
-                    if !label_values.is_empty() {
-                        // Manually build JSON: { "LABEL": { "raw": "value" }, ... }
-                        let mut payload = String::from("{");
-                        let mut first = true;
-                        for (k, v) in label_values.iter() {
-                            if !first { payload.push(','); } else { first = false; }
-                            payload.push('"');
-                            payload.push_str(&k.replace('"', "\""));
-                            payload.push_str("\": {\"raw\": \"");
-                            payload.push_str(&v.replace('"', "\""));
-                            payload.push_str("\"}");
-                        }
-                        payload.push('}');
-                        let topic = format!("tic2mqtt/{}", meter_id);
-                        let _ = publish_tx.send((topic, payload));
-                    }
-                }
-            }
-            tic_mode.handle_label_value(&label, &value);
-        } else {
-            println!("invalid line: {}", line);
-        }
-        if shutdown.load(Ordering::SeqCst) {
-            break;
-        }
-    }
-
     // wait for tasks to finish
-    let _ = serial_handle.join();
-    let _ = mqtt_handle.join();
+    for handle in handles {
+        let _ = handle.join();
+    }
+    for handle in output_handles {
+        let _ = handle.join();
+    }
 }
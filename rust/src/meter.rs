@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+
+use std::time::{Instant, SystemTime};
+
+use tic2mqtt::derived::{Derived, Metric, metric_info};
+use tic2mqtt::tic::{Alert, TicModeHandle, Value};
+use tic2mqtt::tic::frame::{Frame, FrameDecoder, FrameError};
+use tic2mqtt::utils;
+
+use crate::discovery::{self, LabelOverride};
+use crate::output::Outputs;
+use crate::state::StateFile;
+use crate::stats::MeterStats;
+use crate::topics::Topics;
+
+/// A decoded frame of a meter, with its JSON state payload and its typed values
+#[derive(Debug, PartialEq)]
+pub struct FrameUpdate {
+    /// Meter name used in topics
+    pub meter: String,
+    /// TIC mode, "historique" or "standard"
+    pub mode: &'static str,
+    /// When the frame was received
+    pub time: SystemTime,
+    /// Meter date (DATE) in ISO 8601, e.g. "2025-01-15T13:00:00+01:00"
+    pub date: Option<String>,
+    /// Decoded value of each label, sorted by label
+    pub values: Vec<(String, Value)>,
+    pub metrics: Vec<Metric>,
+    /// JSON state payload, see `state_payload`
+    pub state: String,
+}
+
+/// What a meter sends to the outputs: a decoded frame, or a JSON alert event
+#[derive(Clone, Debug, PartialEq)]
+pub enum Update {
+    Frame(Arc<FrameUpdate>),
+    Event(String),
+}
+
+/// A configured meter: its decoding state and how it is published
+#[derive(Clone)]
+pub struct Meter {
+    pub mode: TicModeHandle,
+    /// Configured meter name, replacing the meter address (ADCO/ADSC) in topics
+    pub name: Option<String>,
+    pub overrides: Arc<HashMap<String, LabelOverride>>,
+    /// Labels and derived metrics received so far, including the warnings of past frames
+    pub seen: Arc<Mutex<HashSet<String>>>,
+    pub derived: Arc<Mutex<Derived>>,
+    pub stats: Arc<MeterStats>,
+    /// Last frame sent to the outputs
+    pub last_frame: Arc<Mutex<Option<Arc<FrameUpdate>>>>,
+}
+
+impl Meter {
+    pub fn new(mode: TicModeHandle, name: Option<String>, overrides: Arc<HashMap<String, LabelOverride>>, derived: Derived) -> Self {
+        Meter { mode, name, overrides, seen: Arc::default(), derived: Arc::new(Mutex::new(derived)), stats: Arc::default(), last_frame: Arc::default() }
+    }
+
+    /// Labels of the TIC mode, the derived metrics enabled, and the counters computed so far
+    pub fn labels(&self) -> Vec<String> {
+        let metrics = self.derived.lock().unwrap_or_else(|e| e.into_inner()).ids();
+        let mut labels: Vec<String> = self.mode.labels().into_iter().chain(metrics.into_iter().map(String::from)).collect();
+        for label in self.seen_labels() {
+            if metric_info(&label).is_some() && !labels.contains(&label) { labels.push(label); }
+        }
+        labels
+    }
+
+    pub fn seen_labels(&self) -> Vec<String> {
+        self.seen.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+
+    /// Meter name used in topics: the configured name, else the meter address once received
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.mode.get_meter_id())
+    }
+
+    /// Discovery configs (topic, payload) of the given labels and derived metrics, none until the meter name is known
+    pub fn discovery_messages(&self, labels: &[String], topics: &Topics) -> Vec<(String, String)> {
+        let meter = self.name();
+        if meter.is_empty() { return Vec::new(); }
+        self.mode.with(|mode| {
+            labels
+                .iter()
+                .filter(|label| self.overrides.get(*label).and_then(|o| o.enabled) != Some(false))
+                .flat_map(|label| discovery::get_discovery_messages(mode, label, &meter, topics, self.overrides.get(label)))
+                .chain(std::iter::once(discovery::get_event_discovery_message(mode, &meter, topics)))
+                .collect()
+        })
+    }
+
+    /// Discovery config topics of the previous layout, none until the meter address is known
+    pub fn legacy_discovery_topics(&self, topics: &Topics) -> Vec<String> {
+        self.mode.with(|mode| discovery::legacy_config_topics(mode, topics))
+    }
+}
+
+/// Build the JSON state payload of the current frame: { "LABEL": { "raw": "value", <decoded fields> }, ... }, sorted by label,
+/// followed by the derived metrics: "active_power": { "value": 1500 }
+pub fn state_payload(tic_mode: &TicModeHandle, metrics: &[Metric]) -> Option<String> {
+    let label_values: BTreeMap<_, _> = tic_mode.get_label_values().into_iter().collect();
+    if label_values.is_empty() {
+        return None;
+    }
+    let mut payload = String::from("{");
+    let mut first = true;
+    for (k, v) in label_values.iter() {
+        if !first { payload.push(','); } else { first = false; }
+        payload.push('"');
+        payload.push_str(&utils::json_escape(k));
+        payload.push_str("\": {\"raw\": \"");
+        payload.push_str(&utils::json_escape(&v.value));
+        payload.push('"');
+        for (field, value) in tic_mode.decoded_fields(k) {
+            payload.push_str(&format!(", \"{}\": {}", field, value.to_json()));
+        }
+        payload.push('}');
+    }
+    for metric in metrics {
+        payload.push_str(&format!(",\"{}\": {{\"value\": {}}}", metric.id, metric.value.to_json()));
+    }
+    payload.push('}');
+    Some(payload)
+}
+
+/// Frame update of the current values of a meter, none before its first frame
+fn frame_update(meter: &Meter, name: String, metrics: Vec<Metric>, time: SystemTime) -> Option<FrameUpdate> {
+    let state = state_payload(&meter.mode, &metrics)?;
+    let (mode, date, values) = meter.mode.with(|mode| {
+        let mut values: Vec<(String, Value)> = mode.label_values().iter().map(|(l, lv)| (l.clone(), mode.decode_value(l, &lv.value))).collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        let date = mode.label_values().get("DATE").and_then(|lv| lv.timestamp.clone());
+        (mode.get_mode_name(), date, values)
+    });
+    Some(FrameUpdate { meter: name, mode, time, date, values, metrics, state })
+}
+
+/// JSON alert event: {"event_type": "alert_start" or "alert_end", "alert": id, "label": ..., "value": ..., "timestamp": ...}
+pub fn event_payload(event_type: &str, alert: &Alert, time: SystemTime) -> String {
+    format!(
+        "{{\"event_type\": \"{}\", \"alert\": \"{}\", \"label\": \"{}\", \"value\": \"{}\", \"timestamp\": \"{}\"}}",
+        event_type,
+        utils::json_escape(&alert.id),
+        utils::json_escape(&alert.label),
+        utils::json_escape(&alert.value),
+        utils::format_timestamp(time)
+    )
+}
+
+/// Decode the lines of one meter, and send its frames and alert events to the outputs, with the meter index.
+/// The consumption counters are restored from the state file once the meter name is known, and saved on each new period.
+pub fn run(index: usize, meter: Meter, lines: Receiver<String>, outputs: Outputs, state_file: Option<Arc<StateFile>>, shutdown: &Arc<AtomicBool>) {
+    let mut decoder = FrameDecoder::new();
+    let mut restored = false;
+    // Alerts of the previous frame
+    let mut active: Vec<Alert> = Vec::new();
+    let mut publish = |frame: Frame| {
+        meter.stats.frames.fetch_add(1, Ordering::Relaxed);
+        meter.mode.handle_frame(&frame);
+        let name = meter.name();
+        let metrics = {
+            let mut derived = meter.derived.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(state_file) = state_file.as_ref().filter(|_| !restored && !name.is_empty()) {
+                derived.set_counters(state_file.get(&name));
+                restored = true;
+            }
+            meter.mode.with(|mode| derived.update(mode, Instant::now(), SystemTime::now()));
+            if let Some(state_file) = state_file.as_ref().filter(|_| restored && derived.counters_changed()) {
+                state_file.save(&name, derived.counters());
+            }
+            derived.metrics()
+        };
+        let mut seen = meter.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.extend(frame.groups.iter().map(|g| g.label.clone()));
+        seen.extend(metrics.iter().map(|m| m.id.clone()));
+        drop(seen);
+        if name.is_empty() { return; }
+        let now = SystemTime::now();
+        if let Some(update) = frame_update(&meter, name, metrics, now) {
+            let update = Arc::new(update);
+            *meter.last_frame.lock().unwrap_or_else(|e| e.into_inner()) = Some(update.clone());
+            outputs.send(index, Update::Frame(update));
+        }
+        let alerts = meter.mode.alerts();
+        for alert in alerts.iter().filter(|a| !active.iter().any(|b| b.id == a.id)) {
+            log::warn!("[{}] alert {} ({} {})", meter.name(), alert.id, alert.label, alert.value);
+            outputs.send(index, Update::Event(event_payload("alert_start", alert, now)));
+        }
+        for alert in active.iter().filter(|a| !alerts.iter().any(|b| b.id == a.id)) {
+            log::info!("[{}] alert {} ended", meter.name(), alert.id);
+            outputs.send(index, Update::Event(event_payload("alert_end", alert, now)));
+        }
+        active = alerts;
+    };
+    while let Ok(line) = lines.recv() {
+        for result in decoder.push_line(&line) {
+            match result {
+                Ok(frame) => publish(frame),
+                Err(e) => {
+                    let counter = match e {
+                        FrameError::Checksum { .. } => &meter.stats.checksum_errors,
+                        FrameError::Malformed(_) => &meter.stats.malformed_groups,
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    log::warn!("[{}] {}", Some(meter.name()).filter(|n| !n.is_empty()).unwrap_or_else(|| format!("meter {}", index)), e);
+                }
+            }
+        }
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    // A stream without frame delimiters ends with a complete frame
+    if let Some(frame) = decoder.finish() {
+        publish(frame);
+    }
+}
@@ -1,79 +1,320 @@
 use paho_mqtt as mqtt;
-use std::sync::mpsc::Receiver;
-use crate::tic::TicModeHandle;
+use std::collections::HashSet;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tic2mqtt::derived::ProductionIndex;
+
+use crate::meter::{Meter, Update};
+use crate::output::{OutputSink, Updates};
+use crate::stats::MqttStats;
+use crate::topics::Topics;
+
+/// A meter is reported offline when no frame was received for this long
+pub const METER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Delay before reconnecting to the broker, doubled on each failure up to `MAX_RETRY_DELAY`
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Which labels are announced through Home Assistant MQTT discovery
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiscoveryPolicy {
+    /// Every label known to the TIC mode
+    All,
+    /// Only labels actually received from the meter
+    Seen,
+    /// No discovery messages
+    None,
+}
+
+impl DiscoveryPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "all" => Some(DiscoveryPolicy::All),
+            "seen" => Some(DiscoveryPolicy::Seen),
+            "none" => Some(DiscoveryPolicy::None),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiscoveryPolicy::All => "all",
+            DiscoveryPolicy::Seen => "seen",
+            DiscoveryPolicy::None => "none",
+        }
+    }
+}
+
+/// TLS settings, used with ssl:// or mqtts:// servers
+pub struct TlsConfig {
+    /// CA certificates (PEM), the system trust store otherwise
+    pub ca: Option<String>,
+    /// Client certificate (PEM)
+    pub cert: Option<String>,
+    /// Client private key (PEM)
+    pub key: Option<String>,
+    /// Passphrase of the client private key, empty if none
+    pub key_password: String,
+}
 
 pub struct MqttConfig {
     pub server: String,
     pub client_id: String,
     pub username: String,
     pub password: String,
+    pub tls: Option<TlsConfig>,
+    pub qos: i32,
+    pub topics: Topics,
+    pub discovery: DiscoveryPolicy,
+}
+
+/// Publishing state of a meter
+struct MeterState {
+    meter: Meter,
+    last_frame: Option<Instant>,
+    online: bool,
+    /// Whether the discovery configs of the previous topic layout were cleared
+    legacy_cleared: bool,
 }
 
 pub struct MqttPublisher {
     config: MqttConfig,
-    rx: Receiver<(String, String)>,
-    mode: TicModeHandle,
+    meters: Vec<MeterState>,
+    /// Discovery config topics already published
+    announced: HashSet<String>,
+    stats: Arc<MqttStats>,
+    /// Topic of the production index, and where it goes
+    production: Option<(String, Arc<ProductionIndex>)>,
 }
 
 impl MqttPublisher {
-    pub fn new(config: MqttConfig, rx: Receiver<(String, String)>, mode: TicModeHandle) -> Self {
-        MqttPublisher { config, rx, mode }
+    pub fn new(config: MqttConfig, meters: Vec<Meter>) -> Self {
+        let meters = meters.into_iter().map(|meter| MeterState { meter, last_frame: None, online: false, legacy_cleared: false }).collect();
+        MqttPublisher { config, meters, announced: HashSet::new(), stats: Arc::default(), production: None }
+    }
+
+    /// Count the connections and the publishes in `stats`
+    pub fn with_stats(mut self, stats: Arc<MqttStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Subscribe to the production index published on `topic`, in Wh
+    pub fn with_production(mut self, topic: String, index: Arc<ProductionIndex>) -> Self {
+        self.production = Some((topic, index));
+        self
+    }
+
+    /// Publish a message, and count it
+    fn publish(&self, cli: &mqtt::Client, msg: mqtt::Message) -> mqtt::Result<()> {
+        let start = Instant::now();
+        let result = cli.publish(msg);
+        self.stats.published(start.elapsed(), result.is_ok());
+        result
+    }
+
+    /// Clear the retained discovery configs of the previous topic layout, once the meter address is known, so that
+    /// Home Assistant drops the entities they announced
+    fn clear_legacy_discovery(&mut self, cli: &mqtt::Client, index: usize) {
+        if self.meters[index].legacy_cleared { return; }
+        let topics = self.meters[index].meter.legacy_discovery_topics(&self.config.topics);
+        if topics.is_empty() { return; }
+        for topic in topics {
+            if let Err(e) = self.publish(cli, mqtt::Message::new_retained(&topic, "", self.config.qos)) {
+                log::error!("[MQTT] discovery publish failed: {}", e);
+                return;
+            }
+        }
+        self.meters[index].legacy_cleared = true;
     }
 
-    pub fn run(&mut self) {
-        // Parse server as host:port or tcp://host:port
-        let server = self.config.server.trim_start_matches("tcp://");
+    /// Publish discovery configs of a meter not sent yet, according to the discovery policy
+    fn publish_discovery(&mut self, cli: &mqtt::Client, index: usize) {
+        self.clear_legacy_discovery(cli, index);
+        let meter = &self.meters[index].meter;
+        let mut labels: Vec<String> = match self.config.discovery {
+            DiscoveryPolicy::All => meter.labels(),
+            DiscoveryPolicy::Seen => meter.seen_labels(),
+            DiscoveryPolicy::None => return,
+        };
+        labels.sort();
+        for (topic, payload) in meter.discovery_messages(&labels, &self.config.topics) {
+            if self.announced.contains(&topic) { continue; }
+            let msg = mqtt::MessageBuilder::new()
+                .topic(&topic)
+                .payload(payload)
+                .qos(self.config.qos)
+                .retained(true)
+                .finalize();
+            match self.publish(cli, msg) {
+                Ok(()) => { self.announced.insert(topic); },
+                Err(e) => log::error!("[MQTT] discovery publish failed: {}", e),
+            }
+        }
+    }
+
+    /// Publish the retained availability of a meter when it changes
+    fn publish_availability(&mut self, cli: &mqtt::Client, index: usize, online: bool) {
+        let name = self.meters[index].meter.name();
+        if self.meters[index].online == online || name.is_empty() { return; }
+        let payload = if online { "online" } else { "offline" };
+        log::info!("[MQTT] meter {} is {}", name, payload);
+        let msg = mqtt::Message::new_retained(self.config.topics.availability(&name), payload, self.config.qos);
+        match self.publish(cli, msg) {
+            Ok(()) => self.meters[index].online = online,
+            Err(e) => log::error!("[MQTT] availability publish failed: {}", e),
+        }
+    }
+}
+
+impl OutputSink for MqttPublisher {
+    fn name(&self) -> &'static str { "MQTT" }
+
+    /// Publish the updates, connecting and reconnecting to the broker with increasing delays.
+    /// Updates received while disconnected are dropped.
+    fn run(&mut self, updates: Updates) {
+        // Parse server as host:port or scheme://host:port
+        let (scheme, server) = self.config.server.split_once("://").unwrap_or(("tcp", &self.config.server));
         let mut parts = server.split(':');
         let host = parts.next().unwrap_or("localhost");
-        let port: u16 = parts.next().and_then(|p| p.parse().ok()).unwrap_or(1883);
+        let default_port = if self.config.tls.is_some() { 8883 } else { 1883 };
+        let port: u16 = parts.next().and_then(|p| p.parse().ok()).unwrap_or(default_port);
+        let uri = format!("{}://{}:{}", scheme, host, port);
 
         let create_opts = mqtt::CreateOptionsBuilder::new()
-            .server_uri(format!("tcp://{}:{}", host, port))
+            .server_uri(&uri)
             .client_id(&self.config.client_id)
             .finalize();
 
-        let cli = mqtt::Client::new(create_opts).expect("Failed to create MQTT client");
+        let cli = match mqtt::Client::new(create_opts) {
+            Ok(cli) => cli,
+            Err(e) => {
+                log::error!("[MQTT] cannot create the client of {}: {}, MQTT output disabled", uri, e);
+                return;
+            }
+        };
 
         // Set credentials if provided
         let mut conn_opts_builder = mqtt::ConnectOptionsBuilder::new();
         if !self.config.username.is_empty() {
             conn_opts_builder.user_name(&self.config.username).password(&self.config.password);
         }
-        let conn_opts = conn_opts_builder.keep_alive_interval(std::time::Duration::from_secs(5)).finalize();
-
-        cli.connect(conn_opts).expect("Failed to connect to MQTT broker");
-
-        // Immediately send discovery messages after connect, with retain flag
-        let disco = self.mode.get_all_discovery_messages();
-        for (topic, payload) in disco {
-            let msg = mqtt::MessageBuilder::new()
-                .topic(topic)
-                .payload(payload)
-                .qos(1)
-                .retained(true)
-                .finalize();
-            if let Err(e) = cli.publish(msg) {
-                eprintln!("[MQTT] discovery publish failed: {}", e);
+        if let Some(tls) = &self.config.tls {
+            let mut ssl_opts = mqtt::SslOptionsBuilder::new();
+            let loaded = tls.ca.as_ref().map_or(Ok(()), |ca| ssl_opts.trust_store(ca).map(|_| ()))
+                .and_then(|_| tls.cert.as_ref().map_or(Ok(()), |cert| ssl_opts.key_store(cert).map(|_| ())))
+                .and_then(|_| tls.key.as_ref().map_or(Ok(()), |key| ssl_opts.private_key(key).map(|_| ())));
+            if let Err(e) = loaded {
+                log::error!("[MQTT] cannot load the TLS certificates: {}, MQTT output disabled", e);
+                return;
+            }
+            if !tls.key_password.is_empty() {
+                ssl_opts.private_key_password(&tls.key_password);
             }
+            conn_opts_builder.ssl_options(ssl_opts.finalize());
         }
+        // Production index, received on its own thread
+        if let Some((topic, index)) = self.production.clone() {
+            let incoming = cli.start_consuming();
+            std::thread::spawn(move || {
+                for msg in incoming.iter().flatten() {
+                    match msg.payload_str().trim().parse::<f64>() {
+                        Ok(wh) if wh.is_finite() && wh >= 0.0 => index.set(wh.round() as i64),
+                        _ => log::warn!("[MQTT] {}: invalid production index '{}', expected Wh", topic, msg.payload_str()),
+                    }
+                }
+            });
+        }
+
+        // The broker marks the bridge offline if the connection is lost
+        let status_topic = self.config.topics.bridge_availability();
+        conn_opts_builder.will_message(mqtt::Message::new_retained(&status_topic, "offline", self.config.qos));
+        let conn_opts = conn_opts_builder.keep_alive_interval(Duration::from_secs(5)).finalize();
 
-        loop {
-            // Process outgoing publishes (now topic is full frame topic, value is JSON)
-            match self.rx.try_recv() {
-                Ok((topic, payload)) => {
-                    let msg = mqtt::Message::new(topic, payload, 1);
-                    if let Err(e) = cli.publish(msg) {
-                        eprintln!("[MQTT] publish error: {}", e);
-                        break;
+        'session: loop {
+            let mut delay = RETRY_DELAY;
+            loop {
+                match cli.connect(conn_opts.clone()) {
+                    Ok(_) => break,
+                    Err(e) => log::warn!("[MQTT] cannot connect to {}: {}, retrying in {} s", uri, e, delay.as_secs()),
+                }
+                // Wait for the next attempt, unless the meters are done
+                let deadline = Instant::now() + delay;
+                loop {
+                    match updates.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                        Ok(_) => {}
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
                     }
                 }
-                Err(std::sync::mpsc::TryRecvError::Empty) => {},
-                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                    println!("publisher channel closed");
-                    break;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            self.stats.connects.fetch_add(1, Ordering::Relaxed);
+            self.stats.connected.store(true, Ordering::Relaxed);
+            log::info!("[MQTT] connected to {}", uri);
+            if let Err(e) = self.publish(&cli, mqtt::Message::new_retained(&status_topic, "online", self.config.qos)) {
+                log::error!("[MQTT] availability publish failed: {}", e);
+            }
+            if let Some((topic, _)) = &self.production
+                && let Err(e) = cli.subscribe(topic, self.config.qos)
+            {
+                log::error!("[MQTT] cannot subscribe to {}: {}", topic, e);
+            }
+
+            // Send discovery messages right after connect, for meters already known
+            for index in 0..self.meters.len() {
+                self.publish_discovery(&cli, index);
+            }
+
+            // Process outgoing frames, and check for silent meters in between
+            loop {
+                let msg = match updates.recv_timeout(Duration::from_secs(1)) {
+                    Ok((index, Update::Frame(frame))) => {
+                        self.publish_discovery(&cli, index);
+                        self.meters[index].last_frame = Some(Instant::now());
+                        self.publish_availability(&cli, index, true);
+                        mqtt::Message::new(self.config.topics.state(&frame.meter), frame.state.as_str(), self.config.qos)
+                    }
+                    Ok((index, Update::Event(payload))) => {
+                        mqtt::Message::new(self.config.topics.event(&self.meters[index].meter.name()), payload, self.config.qos)
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Lost without a publish to notice it, e.g. while the meters are silent
+                        if !cli.is_connected() {
+                            log::error!("[MQTT] connection lost, reconnecting");
+                            self.stats.connected.store(false, Ordering::Relaxed);
+                            continue 'session;
+                        }
+                        for index in 0..self.meters.len() {
+                            if self.meters[index].last_frame.is_some_and(|t| t.elapsed() > METER_TIMEOUT) {
+                                self.publish_availability(&cli, index, false);
+                            }
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        log::info!("[MQTT] publisher channel closed");
+                        break 'session;
+                    }
+                };
+                if let Err(e) = self.publish(&cli, msg) {
+                    log::error!("[MQTT] publish error: {}, reconnecting", e);
+                    self.stats.connected.store(false, Ordering::Relaxed);
+                    cli.disconnect(None).ok();
+                    continue 'session;
                 }
             }
         }
+
+        // Clean shutdown: meters and bridge go offline
+        for index in 0..self.meters.len() {
+            self.publish_availability(&cli, index, false);
+        }
+        cli.publish(mqtt::Message::new_retained(&status_topic, "offline", self.config.qos)).ok();
         cli.disconnect(None).ok();
+        cli.stop_consuming();
+        self.stats.connected.store(false, Ordering::Relaxed);
     }
 }
@@ -0,0 +1,160 @@
+//! Outputs of the meters: each sink, MQTT, InfluxDB, JSON lines, CSV, SQLite or the live stream, receives every update on its own
+//! thread, through its own bounded queue, so that a slow sink never stalls the meters nor the other sinks.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::meter::Update;
+
+/// Updates waiting for a sink, the next ones are dropped once full
+pub const QUEUE_SIZE: usize = 1000;
+
+/// Kind of output, as enabled in the configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputKind {
+    Mqtt,
+    Influx,
+    Json,
+    Csv,
+    Sqlite,
+}
+
+impl OutputKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "mqtt" => Some(OutputKind::Mqtt),
+            "influx" => Some(OutputKind::Influx),
+            "json" => Some(OutputKind::Json),
+            "csv" => Some(OutputKind::Csv),
+            "sqlite" => Some(OutputKind::Sqlite),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputKind::Mqtt => "mqtt",
+            OutputKind::Influx => "influx",
+            OutputKind::Json => "json",
+            OutputKind::Csv => "csv",
+            OutputKind::Sqlite => "sqlite",
+        }
+    }
+}
+
+/// A destination of the frames and alert events of the meters
+pub trait OutputSink: Send {
+    /// Name in logs and metrics, e.g. "MQTT"
+    fn name(&self) -> &'static str;
+
+    /// Handle the updates, with the index of their meter, until the channel is closed
+    fn run(&mut self, updates: Updates);
+}
+
+/// Counters of the queue of a sink
+pub struct OutputStats {
+    pub name: &'static str,
+    /// Updates dropped as the queue was full
+    pub dropped: AtomicU64,
+    /// Updates waiting in the queue
+    pub queued: AtomicUsize,
+    /// Whether the last update was dropped, to log once per overflow
+    full: AtomicBool,
+}
+
+/// Updates received by a sink, counted out of its queue
+pub struct Updates {
+    rx: Receiver<(usize, Update)>,
+    stats: Arc<OutputStats>,
+}
+
+impl Updates {
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<(usize, Update), RecvTimeoutError> {
+        let update = self.rx.recv_timeout(timeout)?;
+        self.stats.queued.fetch_sub(1, Ordering::Relaxed);
+        Ok(update)
+    }
+}
+
+/// Blocks for the next update, until the channel is closed
+impl Iterator for Updates {
+    type Item = (usize, Update);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let update = self.rx.recv().ok()?;
+        self.stats.queued.fetch_sub(1, Ordering::Relaxed);
+        Some(update)
+    }
+}
+
+struct Queue {
+    tx: SyncSender<(usize, Update)>,
+    stats: Arc<OutputStats>,
+}
+
+/// Sends the updates of the meters to every sink, without blocking
+#[derive(Clone)]
+pub struct Outputs {
+    queues: Arc<Vec<Queue>>,
+}
+
+impl Outputs {
+    pub fn send(&self, index: usize, update: Update) {
+        for queue in self.queues.iter() {
+            // Counted before, as the sink may receive it at once
+            queue.stats.queued.fetch_add(1, Ordering::Relaxed);
+            match queue.tx.try_send((index, update.clone())) {
+                Ok(()) => {
+                    if queue.stats.full.swap(false, Ordering::Relaxed) {
+                        log::info!("[{}] output queue drained", queue.stats.name);
+                    }
+                }
+                Err(TrySendError::Full(_)) => {
+                    queue.stats.queued.fetch_sub(1, Ordering::Relaxed);
+                    queue.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                    if !queue.stats.full.swap(true, Ordering::Relaxed) {
+                        log::warn!("[{}] output queue full, dropping updates", queue.stats.name);
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => { queue.stats.queued.fetch_sub(1, Ordering::Relaxed); }
+            }
+        }
+    }
+
+    /// Counters of each sink
+    pub fn stats(&self) -> Vec<Arc<OutputStats>> {
+        self.queues.iter().map(|q| q.stats.clone()).collect()
+    }
+}
+
+/// Run each sink on its own thread; they stop once every `Outputs` is dropped
+pub fn spawn(sinks: Vec<Box<dyn OutputSink>>) -> (Outputs, Vec<JoinHandle<()>>) {
+    let mut queues = Vec::new();
+    let mut handles = Vec::new();
+    for mut sink in sinks {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        let stats = Arc::new(OutputStats { name: sink.name(), dropped: AtomicU64::new(0), queued: AtomicUsize::new(0), full: AtomicBool::new(false) });
+        let updates = Updates { rx, stats: stats.clone() };
+        queues.push(Queue { tx, stats });
+        handles.push(std::thread::spawn(move || {
+            sink.run(updates);
+            log::debug!("[{}] output stopped", sink.name());
+        }));
+    }
+    (Outputs { queues: Arc::new(queues) }, handles)
+}
+
+/// Shift <path>.N to <path>.N+1, up to <path>.<keep>, and <path> to <path>.1
+pub fn rotate(path: &str, keep: usize) -> io::Result<()> {
+    for n in (1..keep).rev() {
+        let from = format!("{}.{}", path, n);
+        if std::path::Path::new(&from).exists() {
+            std::fs::rename(&from, format!("{}.{}", path, n + 1))?;
+        }
+    }
+    std::fs::rename(path, format!("{}.1", path))
+}
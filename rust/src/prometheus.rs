@@ -0,0 +1,125 @@
+//! Prometheus exposition of the last values of the meters, and of the internal counters.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tic2mqtt::derived::metric_info;
+use tic2mqtt::tic::Value;
+
+use crate::http::{self, Request, Response};
+use crate::meter::Meter;
+use crate::output::OutputStats;
+use crate::stats::MqttStats;
+
+/// Quote a label value: backslash, double quote and line feed are escaped
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// One family of samples, rendered with its HELP and TYPE lines
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    samples: Vec<String>,
+}
+
+impl Family {
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+        Family { name, kind, help, samples: Vec::new() }
+    }
+
+    fn push(&mut self, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        let labels: Vec<String> = labels.iter().map(|(k, v)| format!("{}={}", k, quote(v))).collect();
+        if labels.is_empty() {
+            self.samples.push(format!("{} {}", self.name, value));
+        } else {
+            self.samples.push(format!("{}{{{}}} {}", self.name, labels.join(","), value));
+        }
+    }
+
+    fn counter(&mut self, labels: &[(&str, &str)], counter: &AtomicU64) {
+        self.push(labels, counter.load(Ordering::Relaxed));
+    }
+
+    fn render(&self, out: &mut String) {
+        if self.samples.is_empty() { return; }
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", self.name, self.help, self.name, self.kind));
+        for sample in &self.samples {
+            out.push_str(sample);
+            out.push('\n');
+        }
+    }
+}
+
+/// Exposition of the meters whose name is known, of the MQTT publisher if enabled and of the output queues
+pub fn render(meters: &[Meter], mqtt: Option<&MqttStats>, outputs: &[Arc<OutputStats>]) -> String {
+    let mut gauges = Family::new("tic_value", "gauge", "Last value of a numeric TIC label or derived metric");
+    let mut indexes = Family::new("tic_value_total", "counter", "Last value of a TIC index or derived counter");
+    let mut frames = Family::new("tic2mqtt_frames_total", "counter", "Frames decoded");
+    let mut checksums = Family::new("tic2mqtt_checksum_errors_total", "counter", "Groups dropped on a checksum error");
+    let mut malformed = Family::new("tic2mqtt_malformed_groups_total", "counter", "Lines dropped as malformed groups");
+    let mut opens = Family::new("tic2mqtt_serial_opens_total", "counter", "Serial port opens, reopens after an error included");
+    for meter in meters {
+        let name = meter.name();
+        if name.is_empty() { continue; }
+        meter.mode.with(|mode| {
+            let mut labels: Vec<_> = mode.label_values().iter().collect();
+            labels.sort_by(|a, b| a.0.cmp(b.0));
+            for (label, lv) in labels {
+                let Value::Integer(value) = mode.decode_value(label, &lv.value) else { continue };
+                let family = if mode.get_ha_state_class(label) == Some("total_increasing") { &mut indexes } else { &mut gauges };
+                family.push(&[("meter", &name), ("label", label), ("unit", mode.get_ha_unit(label).unwrap_or(""))], value);
+            }
+        });
+        for metric in meter.derived.lock().unwrap_or_else(|e| e.into_inner()).metrics() {
+            let Some(info) = metric_info(&metric.id) else { continue };
+            let family = if info.state_class == Some("total_increasing") { &mut indexes } else { &mut gauges };
+            let labels = [("meter", name.as_str()), ("label", &metric.id), ("unit", info.unit.unwrap_or(""))];
+            match metric.value {
+                Value::Integer(value) => family.push(&labels, value),
+                Value::Float(value) => family.push(&labels, value),
+                Value::Bool(_) | Value::Text(_) => {}
+            }
+        }
+        frames.counter(&[("meter", &name)], &meter.stats.frames);
+        checksums.counter(&[("meter", &name)], &meter.stats.checksum_errors);
+        malformed.counter(&[("meter", &name)], &meter.stats.malformed_groups);
+        opens.counter(&[("meter", &name)], &meter.stats.serial_opens);
+    }
+
+    // Left out without MQTT output, rather than zeros looking like a stalled publisher
+    let mut connects = Family::new("tic2mqtt_mqtt_connects_total", "counter", "Connections to the MQTT broker");
+    let mut errors = Family::new("tic2mqtt_mqtt_publish_errors_total", "counter", "MQTT publishes failed");
+    let mut latency = Family::new("tic2mqtt_mqtt_publish_seconds", "summary", "Time spent publishing to the MQTT broker");
+    if let Some(mqtt) = mqtt {
+        connects.counter(&[], &mqtt.connects);
+        errors.counter(&[], &mqtt.publish_errors);
+        // A summary without quantiles
+        latency.samples.push(format!("{}_sum {}", latency.name, mqtt.publish_micros.load(Ordering::Relaxed) as f64 / 1e6));
+        latency.samples.push(format!("{}_count {}", latency.name, mqtt.publishes.load(Ordering::Relaxed)));
+    }
+    let mut dropped = Family::new("tic2mqtt_output_dropped_total", "counter", "Updates dropped as the queue of an output was full");
+    for output in outputs {
+        dropped.counter(&[("output", output.name)], &output.dropped);
+    }
+
+    let mut out = String::new();
+    for family in [gauges, indexes, frames, checksums, malformed, opens, connects, errors, latency, dropped] {
+        family.render(&mut out);
+    }
+    out
+}
+
+/// Response of the metrics endpoint
+pub fn response(meters: &[Meter], mqtt: Option<&MqttStats>, outputs: &[Arc<OutputStats>]) -> Response {
+    Response::new(200, "text/plain; version=0.0.4; charset=utf-8", render(meters, mqtt, outputs))
+}
+
+/// Serve the exposition on /metrics
+pub fn spawn(addr: &str, meters: Vec<Meter>, mqtt: Option<Arc<MqttStats>>, outputs: Vec<Arc<OutputStats>>) -> std::io::Result<()> {
+    http::spawn(addr, move |request: &Request| match request.path.as_str() {
+        "/metrics" => response(&meters, mqtt.as_deref(), &outputs),
+        _ => Response::not_found(),
+    })
+}
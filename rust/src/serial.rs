@@ -1,80 +1,141 @@
+use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::capture::{self, Recorder, Tee};
+use crate::config::Input;
+use crate::stats::MeterStats;
 
 pub struct SerialReader {
-    device: Option<String>,
+    input: Input,
     tx: Sender<String>,
     baudrate: u32,
+    /// Capture file of the raw bytes received
+    record: Option<String>,
+    stats: Arc<MeterStats>,
+}
+
+/// Sleep until `deadline`, or shutdown
+fn sleep_until(deadline: Instant, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::SeqCst) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() { break; }
+        std::thread::sleep(remaining.min(Duration::from_millis(100)));
+    }
 }
 
 impl SerialReader {
-    pub fn new(device: Option<String>, tx: Sender<String>) -> Self {
-        SerialReader { device, tx, baudrate: 9600 }
+    pub fn new(input: Input, tx: Sender<String>) -> Self {
+        SerialReader { input, tx, baudrate: 9600, record: None, stats: Arc::default() }
     }
 
     pub fn with_baud(mut self, baud: u32) -> Self { self.baudrate = baud; self }
 
-    /// Run serial read task. If device is None, fallback to stdin.
-    pub fn run(&mut self, shutdown: &std::sync::Arc<std::sync::atomic::AtomicBool>) {
-        if let Some(dev) = &self.device {
-            // try to open serial port with serialport crate
-            loop {
-                if shutdown.load(std::sync::atomic::Ordering::SeqCst) { break; }
-                match serialport::new(dev, self.baudrate)
-                    .data_bits(serialport::DataBits::Seven)
-                    .parity(serialport::Parity::Even)
-                    .stop_bits(serialport::StopBits::One)
-                    .timeout(Duration::from_secs(5))
-                    .open()
-                {
-                    Ok(port) => {
-                        let mut reader = BufReader::new(port);
-                        let mut line = String::new();
-                        loop {
-                            line.clear();
-                            match reader.read_line(&mut line) {
-                                Ok(0) => break, // EOF
-                                Ok(_) => {
-                                    let line = line.trim_end_matches(['\r', '\n'].as_ref()).to_string();
-                                    if !line.is_empty() {
-                                        let _ = self.tx.send(line.clone());
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("[Serial] read error: {}. Reopening...", e);
-                                    break;
-                                }
-                            }
-                            if shutdown.load(std::sync::atomic::Ordering::SeqCst) { break; }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("[Serial] open failed: {}. Retrying in 5s...", e);
-                        std::thread::sleep(Duration::from_secs(5));
-                        continue;
+    pub fn with_record(mut self, record: Option<String>) -> Self { self.record = record; self }
+
+    /// Count the serial port opens in the stats of the meter
+    pub fn with_stats(mut self, stats: Arc<MeterStats>) -> Self { self.stats = stats; self }
+
+    /// Send the lines read, without CR/LF, until EOF or shutdown. `pace` is called with each raw line, and skips it if false.
+    fn forward_lines(&self, mut reader: impl BufRead, shutdown: &AtomicBool, mut pace: impl FnMut(&[u8]) -> bool) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        loop {
+            if shutdown.load(Ordering::SeqCst) { return Ok(()); }
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                return Ok(()); // EOF
+            }
+            if !pace(&buf) { continue; }
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if !line.is_empty() {
+                let _ = self.tx.send(line.to_string());
+            }
+        }
+    }
+
+    /// Run serial read task, from the serial port, stdin or a capture file
+    pub fn run(&mut self, shutdown: &Arc<AtomicBool>) {
+        let mut recorder = self.record.take().map(Recorder::new);
+        match self.input.clone() {
+            Input::Serial(dev) => self.run_serial(&dev, recorder.as_mut(), shutdown),
+            Input::Stdin => {
+                let stdin = Tee { inner: std::io::stdin().lock(), recorder: recorder.as_mut() };
+                self.stats.input_open.store(true, Ordering::Relaxed);
+                let _ = self.forward_lines(BufReader::new(stdin), shutdown, |_| true);
+                self.stats.input_open.store(false, Ordering::Relaxed);
+            }
+            Input::Replay { path, speed } => self.run_replay(&path, speed, shutdown),
+        }
+    }
+
+    fn run_serial(&self, dev: &str, mut recorder: Option<&mut Recorder>, shutdown: &AtomicBool) {
+        // try to open serial port with serialport crate
+        loop {
+            if shutdown.load(Ordering::SeqCst) { break; }
+            match serialport::new(dev, self.baudrate)
+                .data_bits(serialport::DataBits::Seven)
+                .parity(serialport::Parity::Even)
+                .stop_bits(serialport::StopBits::One)
+                .timeout(Duration::from_secs(5))
+                .open()
+            {
+                Ok(port) => {
+                    self.stats.serial_opens.fetch_add(1, Ordering::Relaxed);
+                    self.stats.input_open.store(true, Ordering::Relaxed);
+                    let port = Tee { inner: port, recorder: recorder.as_deref_mut() };
+                    let read = self.forward_lines(BufReader::new(port), shutdown, |_| true);
+                    self.stats.input_open.store(false, Ordering::Relaxed);
+                    if let Err(e) = read {
+                        log::warn!("[Serial] {}: read error: {}. Reopening...", dev, e);
                     }
                 }
+                Err(e) => {
+                    log::warn!("[Serial] {}: open failed: {}. Retrying in 5s...", dev, e);
+                    sleep_until(Instant::now() + Duration::from_secs(5), shutdown);
+                }
             }
-        } else {
-            // stdin fallback
-            let stdin = std::io::stdin();
-            let mut reader = stdin.lock();
-            let mut acc = String::new();
-            loop {
-                if shutdown.load(std::sync::atomic::Ordering::SeqCst) { break; }
-                acc.clear();
-                match reader.read_line(&mut acc) {
-                    Ok(0) => break,
-                    Ok(_) => {
-                        let line = acc.trim_end_matches(['\r', '\n'].as_ref()).to_string();
-                        if !line.is_empty() {
-                            let _ = self.tx.send(line);
-                        }
-                    }
-                    Err(_) => break,
+        }
+    }
+
+    /// Feed a raw capture, paced when `speed` is not 0: by its timestamp lines if any, else as sent at the serial speed
+    fn run_replay(&self, path: &str, speed: f64, shutdown: &AtomicBool) {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("[Serial] {}: open failed: {}", path, e);
+                return;
+            }
+        };
+        log::info!("[Serial] replaying {}", path);
+        self.stats.input_open.store(true, Ordering::Relaxed);
+        let start = Instant::now();
+        let mut sent = 0;
+        // First timestamp of the capture, and when it was replayed
+        let mut origin: Option<(SystemTime, Instant)> = None;
+        let pace = |line: &[u8]| {
+            if let Some(time) = capture::parse_marker(line) {
+                if speed > 0.0 {
+                    let (first, at) = *origin.get_or_insert((time, Instant::now()));
+                    let offset = time.duration_since(first).unwrap_or_default();
+                    sleep_until(at + offset.div_f64(speed), shutdown);
                 }
+                return false;
+            }
+            if speed > 0.0 && origin.is_none() {
+                // 10 bits per byte with 7E1 framing
+                sent += line.len();
+                sleep_until(start + Duration::from_secs_f64(sent as f64 * 10.0 / self.baudrate as f64 / speed), shutdown);
             }
+            true
+        };
+        if let Err(e) = self.forward_lines(BufReader::new(file), shutdown, pace) {
+            log::error!("[Serial] {}: read error: {}", path, e);
         }
+        self.stats.input_open.store(false, Ordering::Relaxed);
+        log::info!("[Serial] {}: end of replay", path);
     }
 }
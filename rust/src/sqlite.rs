@@ -0,0 +1,189 @@
+//! SQLite output: a local history of the frames, raw and downsampled per minute, with retention periods.
+//!
+//! Tables, with times in ms since the Unix epoch:
+//! - `frames (meter, time, date, data)`: every frame, `data` being its JSON object as written by the JSON output
+//! - `minutes (meter, time, label, min, max, avg, last, samples)`: the numeric labels and derived metrics of each
+//!   minute, written once the minute is over, and read back by `GET /meters/<id>/history` of the REST API
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OpenFlags, params};
+use tic2mqtt::tic::Value;
+
+use crate::json;
+use crate::meter::{FrameUpdate, Update};
+use crate::output::{OutputSink, Updates};
+
+/// Time between two purges of the rows past their retention
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+/// Minutes returned by a history request at most, a week
+const HISTORY_LIMIT: usize = 7 * 24 * 60;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+    CREATE TABLE IF NOT EXISTS frames (meter TEXT NOT NULL, time INTEGER NOT NULL, date TEXT, data TEXT NOT NULL);
+    CREATE INDEX IF NOT EXISTS frames_meter_time ON frames (meter, time);
+    CREATE TABLE IF NOT EXISTS minutes (
+        meter TEXT NOT NULL, time INTEGER NOT NULL, label TEXT NOT NULL,
+        min REAL NOT NULL, max REAL NOT NULL, avg REAL NOT NULL, last REAL NOT NULL, samples INTEGER NOT NULL,
+        PRIMARY KEY (meter, time, label)
+    ) WITHOUT ROWID;
+";
+
+/// Settings of the SQLite output
+pub struct SqliteConfig {
+    pub path: String,
+    /// How long the frames are kept, forever if zero
+    pub retention: Duration,
+    /// How long the minutes are kept, forever if zero
+    pub minute_retention: Duration,
+}
+
+/// Statistics of a value over a minute
+struct Stat {
+    min: f64,
+    max: f64,
+    sum: f64,
+    last: f64,
+    samples: u32,
+}
+
+/// Row of the `minutes` table, for a meter and a label
+pub struct MinuteRow {
+    /// Start of the minute, in ms
+    pub time: i64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub last: f64,
+    pub samples: i64,
+}
+
+/// Values of a meter in the current minute
+struct Minute {
+    /// Start of the minute, in ms
+    time: i64,
+    stats: BTreeMap<String, Stat>,
+}
+
+pub struct SqliteSink {
+    config: SqliteConfig,
+    /// Current minute of each meter, by name
+    minutes: HashMap<String, Minute>,
+    last_purge: Option<Instant>,
+}
+
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+impl SqliteSink {
+    pub fn new(config: SqliteConfig) -> Self {
+        SqliteSink { config, minutes: HashMap::new(), last_purge: None }
+    }
+
+    fn write(&mut self, conn: &Connection, frame: &FrameUpdate) -> rusqlite::Result<()> {
+        let time = millis(frame.time);
+        conn.execute(
+            "INSERT INTO frames (meter, time, date, data) VALUES (?1, ?2, ?3, ?4)",
+            params![frame.meter, time, frame.date, json::line(frame)],
+        )?;
+
+        let start = time - time.rem_euclid(60_000);
+        if let Some(minute) = self.minutes.remove(&frame.meter) {
+            if minute.time == start {
+                self.minutes.insert(frame.meter.clone(), minute);
+            } else {
+                write_minute(conn, &frame.meter, &minute)?;
+            }
+        }
+        let minute = self.minutes.entry(frame.meter.clone()).or_insert_with(|| Minute { time: start, stats: BTreeMap::new() });
+        let values = frame.values.iter().map(|(label, value)| (label, value));
+        for (label, value) in values.chain(frame.metrics.iter().map(|m| (&m.id, &m.value))) {
+            let value = match value {
+                Value::Integer(v) => *v as f64,
+                Value::Float(v) if v.is_finite() => *v,
+                _ => continue,
+            };
+            let stat = minute.stats.entry(label.clone()).or_insert(Stat { min: value, max: value, sum: 0.0, last: value, samples: 0 });
+            stat.min = stat.min.min(value);
+            stat.max = stat.max.max(value);
+            stat.sum += value;
+            stat.last = value;
+            stat.samples += 1;
+        }
+
+        if self.last_purge.is_none_or(|t| t.elapsed() >= PURGE_INTERVAL) {
+            self.purge(conn)?;
+            self.last_purge = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Delete the rows past their retention
+    fn purge(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let now = millis(SystemTime::now());
+        for (table, retention) in [("frames", self.config.retention), ("minutes", self.config.minute_retention)] {
+            if retention.is_zero() { continue; }
+            let deleted = conn.execute(&format!("DELETE FROM {} WHERE time < ?1", table), [now - retention.as_millis() as i64])?;
+            if deleted > 0 {
+                log::debug!("[SQLite] {} rows of {} deleted", deleted, table);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_minute(conn: &Connection, meter: &str, minute: &Minute) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT OR REPLACE INTO minutes (meter, time, label, min, max, avg, last, samples) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for (label, s) in &minute.stats {
+            insert.execute(params![meter, minute.time, label, s.min, s.max, s.sum / s.samples as f64, s.last, s.samples])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Minutes of a label of a meter from `from` to `to` (excluded), in ms, the first `HISTORY_LIMIT` ones
+pub fn history(path: &str, meter: &str, label: &str, from: i64, to: i64) -> rusqlite::Result<Vec<MinuteRow>> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    conn.busy_timeout(Duration::from_secs(1))?;
+    let mut select = conn.prepare(
+        "SELECT time, min, max, avg, last, samples FROM minutes WHERE meter = ?1 AND label = ?2 AND time >= ?3 AND time < ?4 ORDER BY time LIMIT ?5",
+    )?;
+    select.query_map(params![meter, label, from, to, HISTORY_LIMIT as i64], |row| {
+        Ok(MinuteRow { time: row.get(0)?, min: row.get(1)?, max: row.get(2)?, avg: row.get(3)?, last: row.get(4)?, samples: row.get(5)? })
+    })?.collect()
+}
+
+impl OutputSink for SqliteSink {
+    fn name(&self) -> &'static str { "SQLite" }
+
+    /// Store the frames, alert events are not stored. The minutes in progress are written when the channel closes.
+    fn run(&mut self, updates: Updates) {
+        let conn = match Connection::open(&self.config.path).and_then(|conn| conn.execute_batch(SCHEMA).map(|_| conn)) {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::error!("[SQLite] cannot open {}: {}, SQLite output disabled", self.config.path, e);
+                return;
+            }
+        };
+        log::info!("[SQLite] storing to {}", self.config.path);
+        for (_, update) in updates {
+            let Update::Frame(frame) = update else { continue };
+            if let Err(e) = self.write(&conn, &frame) {
+                log::warn!("[SQLite] {}: {}", self.config.path, e);
+            }
+        }
+        for (meter, minute) in &self.minutes {
+            if let Err(e) = write_minute(&conn, meter, minute) {
+                log::warn!("[SQLite] {}: {}", self.config.path, e);
+            }
+        }
+    }
+}
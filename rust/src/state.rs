@@ -0,0 +1,47 @@
+//! State file of the consumption counters, shared by the meters, so that restarts do not reset them.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use tic2mqtt::derived::CounterState;
+
+/// Counters of each meter by meter name, saved as TOML
+pub struct StateFile {
+    path: String,
+    meters: Mutex<BTreeMap<String, CounterState>>,
+}
+
+impl StateFile {
+    /// Load the state file, a missing or unreadable file starts afresh
+    pub fn load(path: &str) -> StateFile {
+        let meters = match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("state file {}: {}, counters start afresh", path, e);
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                log::warn!("cannot read state file {}: {}, counters start afresh", path, e);
+                BTreeMap::new()
+            }
+        };
+        StateFile { path: path.to_string(), meters: Mutex::new(meters) }
+    }
+
+    pub fn get(&self, meter: &str) -> CounterState {
+        self.meters.lock().unwrap_or_else(|e| e.into_inner()).get(meter).cloned().unwrap_or_default()
+    }
+
+    /// Store the counters of a meter, and rewrite the file atomically
+    pub fn save(&self, meter: &str, counters: &CounterState) {
+        let mut meters = self.meters.lock().unwrap_or_else(|e| e.into_inner());
+        meters.insert(meter.to_string(), counters.clone());
+        let result = toml::to_string(&*meters).map_err(|e| e.to_string()).and_then(|content| {
+            let tmp = format!("{}.tmp", self.path);
+            std::fs::write(&tmp, content).and_then(|_| std::fs::rename(&tmp, &self.path)).map_err(|e| e.to_string())
+        });
+        if let Err(e) = result {
+            log::error!("cannot write state file {}: {}", self.path, e);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Internal counters of the bridge, exposed by the metrics endpoint.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters of one meter, from its serial input and its decoder
+#[derive(Default)]
+pub struct MeterStats {
+    pub frames: AtomicU64,
+    pub checksum_errors: AtomicU64,
+    pub malformed_groups: AtomicU64,
+    /// Serial port opened, the first time included
+    pub serial_opens: AtomicU64,
+    /// Whether the input, serial port, stdin or capture, is being read
+    pub input_open: AtomicBool,
+}
+
+/// Counters of the MQTT publisher
+#[derive(Default)]
+pub struct MqttStats {
+    pub connects: AtomicU64,
+    /// Whether the client is connected to the broker
+    pub connected: AtomicBool,
+    pub publishes: AtomicU64,
+    pub publish_errors: AtomicU64,
+    /// Total time spent publishing, in µs
+    pub publish_micros: AtomicU64,
+}
+
+impl MqttStats {
+    /// Count a publish, and how long it took
+    pub fn published(&self, elapsed: Duration, ok: bool) {
+        self.publishes.fetch_add(1, Ordering::Relaxed);
+        self.publish_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if !ok {
+            self.publish_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
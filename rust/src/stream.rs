@@ -0,0 +1,147 @@
+//! Live stream of the frames and alert events, as server-sent events on `GET /stream` of the embedded HTTP server.
+//! Each frame is a `data:` event holding its JSON object, as written by the JSON lines output, and each alert an
+//! `alert` event. Query parameters restrict the stream: `meter=<id>` to one meter, `labels=PAPP,SINSTS` to some
+//! labels and derived metrics. `MAX_SUBSCRIBERS` clients at most, the next ones get a 503.
+
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::Duration;
+
+use tic2mqtt::utils::json_escape;
+
+use crate::http::{Request, Response};
+use crate::json;
+use crate::meter::{FrameUpdate, Meter, Update};
+use crate::output::{OutputSink, Updates};
+
+/// Updates waiting for a client, the next ones are dropped once full
+const CLIENT_QUEUE_SIZE: usize = 100;
+/// Clients of the stream at once, below `http::MAX_CONNECTIONS` so that the API stays available
+const MAX_SUBSCRIBERS: usize = 16;
+/// Time without update before a comment is sent, so that idle connections are kept and closed ones noticed
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Queue of a connected client
+type Client = SyncSender<(usize, Update)>;
+
+/// Queues of the connected clients
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    clients: Arc<Mutex<Vec<Client>>>,
+    /// Subscriptions not dropped yet, whose queue may still be listed in `clients`
+    active: Arc<AtomicUsize>,
+}
+
+/// Queue of a client, counted until dropped
+struct Subscription {
+    updates: Receiver<(usize, Update)>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Subscribers {
+    /// New queue, none if `MAX_SUBSCRIBERS` are already there
+    fn subscribe(&self) -> Option<Subscription> {
+        if self.active.fetch_add(1, Ordering::Relaxed) >= MAX_SUBSCRIBERS {
+            self.active.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        let (tx, rx) = mpsc::sync_channel(CLIENT_QUEUE_SIZE);
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).push(tx);
+        Some(Subscription { updates: rx, active: self.active.clone() })
+    }
+}
+
+/// Sends every update to the clients of the stream, dropping those not read fast enough
+pub struct StreamSink {
+    subscribers: Subscribers,
+}
+
+impl StreamSink {
+    pub fn new(subscribers: Subscribers) -> Self {
+        StreamSink { subscribers }
+    }
+}
+
+impl OutputSink for StreamSink {
+    fn name(&self) -> &'static str { "Stream" }
+
+    /// Forward the updates, forgetting the clients gone
+    fn run(&mut self, updates: Updates) {
+        for (index, update) in updates {
+            let mut subscribers = self.subscribers.clients.lock().unwrap_or_else(|e| e.into_inner());
+            subscribers.retain(|tx| !matches!(tx.try_send((index, update.clone())), Err(TrySendError::Disconnected(_))));
+        }
+    }
+}
+
+/// Frame restricted to the given labels and derived metrics
+fn filter(frame: &FrameUpdate, labels: &[String]) -> FrameUpdate {
+    FrameUpdate {
+        meter: frame.meter.clone(),
+        mode: frame.mode,
+        time: frame.time,
+        date: frame.date.clone(),
+        values: frame.values.iter().filter(|(label, _)| labels.contains(label)).cloned().collect(),
+        metrics: frame.metrics.iter().filter(|m| labels.contains(&m.id)).cloned().collect(),
+        state: String::new(),
+    }
+}
+
+/// Response of `GET /stream`, subscribing a new client, or of `HEAD /stream`, without subscribing
+pub fn response(request: &Request, meters: &[Meter], subscribers: &Subscribers) -> Response {
+    if request.method == "HEAD" {
+        return Response::events(|_| Ok(()));
+    }
+    // Not checked against the meters, whose name may only be known from their first frame
+    let meter = request.param("meter");
+    let labels: Option<Vec<String>> = request.param("labels")
+        .map(|labels| labels.split(',').map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect());
+    let meters = meters.to_vec();
+    let Some(subscription) = subscribers.subscribe() else {
+        return Response::json(503, "{\"error\": \"too many stream clients\"}\n");
+    };
+    Response::events(move |out| send(out, subscription, &meters, meter, labels))
+}
+
+/// Write the updates of the meters matching to the client, until it goes away or the outputs stop
+fn send(out: &mut TcpStream, subscription: Subscription, meters: &[Meter], meter: Option<String>, labels: Option<Vec<String>>) -> io::Result<()> {
+    let peer = out.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    log::debug!("[HTTP] {}: stream started", peer);
+    // Reconnection delay of the browsers
+    out.write_all(b"retry: 5000\n\n")?;
+    out.flush()?;
+    loop {
+        let event = match subscription.updates.recv_timeout(KEEPALIVE) {
+            Ok((index, update)) => {
+                let name = meters.get(index).map(Meter::name).unwrap_or_default();
+                if meter.as_ref().is_some_and(|m| *m != name) { continue; }
+                match update {
+                    Update::Frame(frame) => match &labels {
+                        Some(labels) => format!("data: {}\n\n", json::line(&filter(&frame, labels))),
+                        None => format!("data: {}\n\n", json::line(&frame)),
+                    },
+                    // {"meter": ..., "event_type": ..., ...}
+                    Update::Event(payload) => {
+                        let fields = payload.strip_prefix('{').unwrap_or(&payload);
+                        format!("event: alert\ndata: {{\"meter\": \"{}\", {}\n\n", json_escape(&name), fields)
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => ": keepalive\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        if let Err(e) = out.write_all(event.as_bytes()).and_then(|_| out.flush()) {
+            log::debug!("[HTTP] {}: stream closed: {}", peer, e);
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! TIC frame parsing and formatting.
+//!
+//! A frame is a sequence of information groups between STX and ETX. Each group is
+//! `LF label SEP [horodate SEP] value SEP checksum CR`, where SEP is a space in
+//! historique mode and a horizontal tab in standard mode.
+
+use std::fmt;
+
+/// Start of frame
+pub const STX: char = '\x02';
+/// End of frame
+pub const ETX: char = '\x03';
+/// Frame interrupted by the meter
+pub const EOT: char = '\x04';
+
+/// Labels opening a frame, used to split streams without STX/ETX
+pub const FRAME_START_LABELS: [&str; 2] = ["ADCO", "ADSC"];
+
+/// One information group of a frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Group {
+    pub label: String,
+    pub value: String,
+    /// Horodate of standard mode groups, e.g. "E250101120000"
+    pub horodate: Option<String>,
+}
+
+/// Error on a group of a frame
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameError {
+    /// The checksum character does not match the group content
+    Checksum { line: String, expected: char, found: char },
+    /// The line is not a valid group
+    Malformed(String),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Checksum { line, expected, found } => {
+                write!(f, "checksum error on {:?}: expected {:?}, found {:?}", line, expected, found)
+            }
+            FrameError::Malformed(line) => write!(f, "malformed group {:?}", line),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// Checksum of a group: sum of the bytes, truncated to 6 bits, plus 0x20
+pub fn checksum(data: &str) -> char {
+    let sum: u32 = data.bytes().map(u32::from).sum();
+    ((sum & 0x3F) as u8 + 0x20) as char
+}
+
+/// Parse a group without its LF/CR, in historique (space separated) or standard (tab separated) format
+pub fn parse_group(line: &str) -> Result<Group, FrameError> {
+    let line = line.trim_matches(['\r', '\n']);
+    let malformed = || FrameError::Malformed(line.to_string());
+    let found = line.chars().last().ok_or_else(malformed)?;
+    let body = &line[..line.len() - found.len_utf8()];
+
+    let (group, expected) = if line.contains('\t') {
+        // Standard: the checksum covers the separator before it
+        let expected = checksum(body);
+        let fields: Vec<&str> = body.strip_suffix('\t').ok_or_else(malformed)?.split('\t').collect();
+        let group = match fields.as_slice() {
+            [label, value] => Group { label: label.to_string(), value: value.to_string(), horodate: None },
+            [label, horodate, value] => Group { label: label.to_string(), value: value.to_string(), horodate: Some(horodate.to_string()) },
+            _ => return Err(malformed()),
+        };
+        (group, expected)
+    } else {
+        // Historique: the checksum stops before the last separator
+        let content = body.strip_suffix(' ').ok_or_else(malformed)?;
+        let (label, value) = content.split_once(' ').ok_or_else(malformed)?;
+        (Group { label: label.to_string(), value: value.to_string(), horodate: None }, checksum(content))
+    };
+    if group.label.is_empty() {
+        return Err(malformed());
+    }
+    if expected != found {
+        return Err(FrameError::Checksum { line: line.to_string(), expected, found });
+    }
+    Ok(group)
+}
+
+/// Format a group with its checksum, without LF and CR, in standard (tab separated) or historique (space separated) format
+pub fn format_group(group: &Group, standard: bool) -> String {
+    let sep = if standard { '\t' } else { ' ' };
+    let mut line = group.label.clone();
+    if let Some(horodate) = group.horodate.as_ref().filter(|_| standard) {
+        line.push(sep);
+        line.push_str(horodate);
+    }
+    line.push(sep);
+    line.push_str(&group.value);
+    if standard {
+        line.push(sep);
+        let ck = checksum(&line);
+        line.push(ck);
+    } else {
+        let ck = checksum(&line);
+        line.push(sep);
+        line.push(ck);
+    }
+    line
+}
+
+/// Convert a horodate "SAAMMJJhhmmss" to ISO 8601, e.g. "E250101120000" to "2025-01-01T12:00:00+02:00".
+/// The season is 'E' (summer, UTC+2) or 'H' (winter, UTC+1), lowercase when the meter clock is degraded.
+pub fn horodate_to_iso(horodate: &str) -> Option<String> {
+    let mut chars = horodate.chars();
+    let season = chars.next()?;
+    let digits = chars.as_str();
+    if digits.len() != 12 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let offset = match season.to_ascii_uppercase() {
+        'E' => "+02:00",
+        'H' => "+01:00",
+        _ => "",
+    };
+    Some(format!(
+        "20{}-{}-{}T{}:{}:{}{}",
+        &digits[0..2], &digits[2..4], &digits[4..6], &digits[6..8], &digits[8..10], &digits[10..12], offset
+    ))
+}
+
+/// A complete frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub groups: Vec<Group>,
+}
+
+impl Frame {
+    pub fn get(&self, label: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.label == label)
+    }
+
+    pub fn value(&self, label: &str) -> Option<&str> {
+        self.get(label).map(|g| g.value.as_str())
+    }
+}
+
+/// Assemble lines (split on LF) into frames.
+///
+/// Frames are delimited by STX/ETX when the stream has them, else a frame starts
+/// with its ADCO/ADSC group, as in text captures.
+#[derive(Default)]
+pub struct FrameDecoder {
+    groups: Vec<Group>,
+    /// STX seen, the stream uses frame delimiters
+    framed: bool,
+    /// Inside a STX..ETX frame
+    in_frame: bool,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one line, returns the frames completed and the groups in error
+    pub fn push_line(&mut self, line: &str) -> Vec<Result<Frame, FrameError>> {
+        let mut out = Vec::new();
+        for (i, part) in line.split([STX, ETX, EOT]).enumerate() {
+            if i > 0 {
+                // The control character before this part
+                let offset: usize = line.split([STX, ETX, EOT]).take(i).map(|p| p.len() + 1).sum::<usize>() - 1;
+                match line[offset..].chars().next() {
+                    Some(STX) => {
+                        self.framed = true;
+                        self.in_frame = true;
+                        self.groups.clear();
+                    }
+                    Some(ETX) => {
+                        if self.in_frame && !self.groups.is_empty() {
+                            out.push(Ok(Frame { groups: std::mem::take(&mut self.groups) }));
+                        }
+                        self.in_frame = false;
+                        self.groups.clear();
+                    }
+                    _ => {
+                        self.in_frame = false;
+                        self.groups.clear();
+                    }
+                }
+            }
+            let part = part.trim_matches(['\r', '\n']);
+            if part.is_empty() {
+                continue;
+            }
+            match parse_group(part) {
+                Ok(group) => {
+                    if self.framed && !self.in_frame {
+                        // Partial frame before the first STX
+                        continue;
+                    }
+                    if !self.framed && FRAME_START_LABELS.contains(&group.label.as_str()) && !self.groups.is_empty() {
+                        out.push(Ok(Frame { groups: std::mem::take(&mut self.groups) }));
+                    }
+                    self.groups.push(group);
+                }
+                Err(e) => out.push(Err(e)),
+            }
+        }
+        out
+    }
+
+    /// End of stream: the pending frame of a stream without STX/ETX
+    pub fn finish(&mut self) -> Option<Frame> {
+        if self.framed || self.groups.is_empty() {
+            self.groups.clear();
+            return None;
+        }
+        Some(Frame { groups: std::mem::take(&mut self.groups) })
+    }
+}
@@ -1,8 +1,8 @@
-use std::any::Any;
-use crate::tic::TicMode;
+use crate::tic::{Alert, TicMode, Value};
 use std::collections::{HashSet, HashMap};
 
 use crate::tic::LabelValue;
+#[derive(Default)]
 pub struct HistoriqueTIC {
     meter_id: String,
     pub label_values: HashMap<String, LabelValue>,
@@ -10,12 +10,11 @@ pub struct HistoriqueTIC {
 
 impl HistoriqueTIC {
     pub const BAUDRATE: u32 = 1200;
-    pub fn new() -> Self { HistoriqueTIC { meter_id: String::new(), label_values: HashMap::new() } }
+    pub fn new() -> Self { Self::default() }
 }
 
 impl TicMode for HistoriqueTIC {
     fn get_mode_name(&self) -> &'static str { "historique" }
-    fn as_any(&self) -> &dyn Any { self }
     fn baudrate(&self) -> u32 {
         Self::BAUDRATE
     }
@@ -30,9 +29,12 @@ impl TicMode for HistoriqueTIC {
         labels.into_iter().map(|s| s.to_string()).collect()
     }
 
+    fn label_values(&self) -> &HashMap<String, LabelValue> { &self.label_values }
+    fn label_values_mut(&mut self) -> &mut HashMap<String, LabelValue> { &mut self.label_values }
+
     fn handle_label_value(&mut self, label: &str, value: &str) {
         if label == "ADCO" {
-            // Publish previous frame if any (handled in main.rs)
+            // Publish previous frame if any (handled by the caller)
             self.set_meter_id(value);
             // Do not clear label_values, just update values in place
         }
@@ -55,11 +57,77 @@ impl TicMode for HistoriqueTIC {
 
     fn get_meter_id(&self) -> String { self.meter_id.clone() }
 
+    /// PPOT: bit n set when the potential of phase n is missing, "00" when all phases are present
+    fn decode_fields(&self, label: &str, raw: &str) -> Vec<(String, Value)> {
+        match (label, u8::from_str_radix(raw, 16)) {
+            ("PPOT", Ok(bits)) => (1..=3).map(|n| (format!("phase{}", n), Value::Bool(bits & (1 << n) == 0))).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn phase(&self, label: &str) -> Option<u8> {
+        let per_phase = ["ADIR1", "ADIR2", "ADIR3", "IINST1", "IINST2", "IINST3", "IMAX1", "IMAX2", "IMAX3"];
+        if !per_phase.contains(&label) { return None; }
+        label.bytes().last().map(|b| b - b'0')
+    }
+
+    /// ADPS: subscribed current exceeded; ADIRx: overcurrent on a phase, in the short frames of three-phase meters
+    fn is_event(&self, label: &str) -> bool {
+        matches!(label, "ADPS" | "ADIR1" | "ADIR2" | "ADIR3")
+    }
+
+    /// One index per tariff period of the tariff option (OPTARIF): BASE, HC/HP, EJP or Tempo, all until it is known
+    fn energy_indexes(&self) -> Vec<&'static str> {
+        let option = self.label_values.get("OPTARIF").map(|lv| lv.value.as_str()).unwrap_or("");
+        match option {
+            "BASE" => vec!["BASE"],
+            "HC.." => vec!["HCHC", "HCHP"],
+            "EJP." => vec!["EJPHN", "EJPHPM"],
+            o if o.starts_with("BBR") => vec!["BBRHCJB", "BBRHPJB", "BBRHCJW", "BBRHPJW", "BBRHCJR", "BBRHPJR"],
+            _ => vec!["BASE", "HCHC", "HCHP", "EJPHN", "EJPHPM", "BBRHCJB", "BBRHPJB", "BBRHCJW", "BBRHPJW", "BBRHCJR", "BBRHPJR"],
+        }
+    }
+
+    /// From the tariff period in progress (PTEC), e.g. "HC.." for HCHC or "HPJB" for BBRHPJB
+    fn active_index(&self) -> Option<String> {
+        let period = self.label_values.get("PTEC")?.value.as_str();
+        Some(match period {
+            "TH.." => "BASE".to_string(),
+            "HC.." => "HCHC".to_string(),
+            "HP.." => "HCHP".to_string(),
+            "HN.." => "EJPHN".to_string(),
+            "PM.." => "EJPHPM".to_string(),
+            p if p.len() == 4 && (p.starts_with("HCJ") || p.starts_with("HPJ")) => format!("BBR{}", p),
+            _ => return None,
+        })
+    }
+
+    /// Overcurrents, and phases without potential
+    fn alerts(&self) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for (label, lv) in &self.label_values {
+            let id = match (label.as_str(), self.phase(label)) {
+                ("ADPS", _) => "overcurrent".to_string(),
+                (_, Some(phase)) if self.is_event(label) => format!("overcurrent_phase{}", phase),
+                _ => continue,
+            };
+            alerts.push(Alert { id, label: label.clone(), value: lv.value.clone() });
+        }
+        if let Some(ppot) = self.label_values.get("PPOT") {
+            for (field, present) in self.decode_fields("PPOT", &ppot.value) {
+                if present == Value::Bool(false) {
+                    alerts.push(Alert { id: format!("missing_{}", field), label: "PPOT".to_string(), value: ppot.value.clone() });
+                }
+            }
+        }
+        alerts
+    }
+
     fn get_ha_device_class(&self, label: &str) -> Option<&'static str> {
-        let apparent_power: HashSet<&str> = ["PAPP", "PREF", "PCOUP", "SINSTS", "SINSTS1", "SINSTS2", "SINSTS3"].into_iter().collect();
-        let current: HashSet<&str> = ["ADIR1", "ADIR2", "ADIR3", "ADPS", "IINST", "IINST1", "IINST2", "IINST3", "IMAX", "IMAX1", "IMAX2", "IMAX3", "ISOUSC"].into_iter().collect();
+        let current: HashSet<&str> = ["ADPS", "IINST", "IINST1", "IINST2", "IINST3", "IMAX", "IMAX1", "IMAX2", "IMAX3", "ISOUSC"].into_iter().collect();
         let energy: HashSet<&str> = ["BASE", "BBRHCJB", "BBRHCJR", "BBRHCJW", "BBRHPJB", "BBRHPJR", "BBRHPJW", "EJPHN", "EJPHPM", "HCHC", "HCHP"].into_iter().collect();
-        if apparent_power.contains(label) { return Some("apparent_power"); }
+        if label == "PAPP" { return Some("apparent_power"); }
+        if label == "PMAX" { return Some("power"); }
         if current.contains(label) { return Some("current"); }
         if energy.contains(label) { return Some("energy"); }
         None
@@ -76,12 +144,15 @@ impl TicMode for HistoriqueTIC {
     fn get_ha_unit(&self, label: &str) -> Option<&'static str> {
         let ampere: HashSet<&str> = ["ADPS", "ADIR1", "ADIR2", "ADIR3", "IINST", "IINST1", "IINST2", "IINST3", "IMAX", "IMAX1", "IMAX2", "IMAX3", "ISOUSC"].into_iter().collect();
         let watt_hour: HashSet<&str> = ["BASE", "BBRHCJB", "BBRHCJR", "BBRHCJW", "BBRHPJB", "BBRHPJR", "BBRHPJW", "EJPHN", "EJPHPM", "HCHC", "HCHP"].into_iter().collect();
-        let volt_ampere: HashSet<&str> = ["PAPP", "PREF", "PCOUP"].into_iter().collect();
-        let watt: HashSet<&str> = ["PMAX"].into_iter().collect();
         if ampere.contains(label) { return Some("A"); }
         if watt_hour.contains(label) { return Some("Wh"); }
-        if volt_ampere.contains(label) { return Some("VA"); }
-        if watt.contains(label) { return Some("W"); }
+        if label == "PAPP" { return Some("VA"); }
+        if label == "PMAX" { return Some("W"); }
         None
     }
+
+    /// IMAXx: maximum current called on each phase, a figure of the meter rather than a measurement
+    fn get_ha_entity_category(&self, label: &str) -> Option<&'static str> {
+        matches!(label, "IMAX" | "IMAX1" | "IMAX2" | "IMAX3").then_some("diagnostic")
+    }
 }
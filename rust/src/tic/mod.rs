@@ -1,13 +1,31 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::utils::sanitize_value;
+use frame::{Frame, Group, horodate_to_iso};
+pub use value::Value;
+
+pub mod frame;
+pub mod historique;
+pub mod standard;
+mod value;
+
+/// Last value received for a label
 #[derive(Clone, Debug, Default)]
 pub struct LabelValue {
     pub value: String,
-    pub timestamp: Option<String>, // ISO 8601 format, e.g. "2025-11-27T02:01:00.000Z"
+    pub timestamp: Option<String>, // ISO 8601 format, e.g. "2025-11-27T02:01:00+01:00"
 }
-use std::sync::{Arc, Mutex};
-use crate::utils::{MQTT_ID_BASE, MQTT_TOPIC_BASE, sanitize_label};
 
-pub mod standard;
-pub mod historique;
+/// An abnormal condition reported by a meter, e.g. an overcurrent, while it lasts
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    /// Condition, e.g. "overcurrent_phase2"
+    pub id: String,
+    /// Label reporting it, and its value
+    pub label: String,
+    pub value: String,
+}
 
 #[derive(Clone)]
 pub enum TicModeEnum {
@@ -15,15 +33,13 @@ pub enum TicModeEnum {
     Historique,
 }
 
+/// Shared handle on the decoding state of one meter
 #[derive(Clone)]
 pub struct TicModeHandle {
     inner: Arc<Mutex<Box<dyn TicMode + Send>>>,
 }
 
 impl TicModeHandle {
-    pub fn baudrate(&self) -> u32 {
-        if let Ok(lock) = self.inner.lock() { lock.baudrate() } else { 9600 }
-    }
     pub fn new(mode: TicModeEnum) -> Self {
         let boxed: Box<dyn TicMode + Send> = match mode {
             TicModeEnum::Standard => Box::new(standard::StandardTIC::new()),
@@ -32,94 +48,125 @@ impl TicModeHandle {
         TicModeHandle { inner: Arc::new(Mutex::new(boxed)) }
     }
 
+    /// Run `f` on the meter state, a panic of another thread holding the lock does not lose it
+    pub fn with<R>(&self, f: impl FnOnce(&mut dyn TicMode) -> R) -> R {
+        let mut lock = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        f(lock.as_mut())
+    }
+
+    pub fn baudrate(&self) -> u32 {
+        self.with(|m| m.baudrate())
+    }
+
+    pub fn get_mode_name(&self) -> &'static str {
+        self.with(|m| m.get_mode_name())
+    }
+
     pub fn handle_label_value(&self, label: &str, value: &str) {
-        if let Ok(mut lock) = self.inner.lock() { lock.handle_label_value(label, value); }
-    }
-
-    pub fn get_label_values(&self) -> std::collections::HashMap<String, String> {
-        if let Ok(lock) = self.inner.lock() {
-            // Downcast to concrete type to access label_values
-            if let Some(h) = lock.as_any().downcast_ref::<crate::tic::historique::HistoriqueTIC>() {
-                return h.label_values.clone();
-            }
-            if let Some(s) = lock.as_any().downcast_ref::<crate::tic::standard::StandardTIC>() {
-                return s.label_values.clone();
-            }
-        }
-        std::collections::HashMap::new()
+        self.with(|m| m.handle_label_value(label, value))
+    }
+
+    pub fn handle_frame(&self, frame: &Frame) {
+        self.with(|m| m.handle_frame(frame))
+    }
+
+    pub fn get_label_values(&self) -> HashMap<String, LabelValue> {
+        self.with(|m| m.label_values().clone())
+    }
+
+    /// Decoded last value of a label
+    pub fn decoded_value(&self, label: &str) -> Option<Value> {
+        self.with(|m| m.label_values().get(label).map(|lv| m.decode_value(label, &lv.value)))
+    }
+
+    /// Fields decoded from the last value of a label
+    pub fn decoded_fields(&self, label: &str) -> Vec<(String, Value)> {
+        self.with(|m| m.label_values().get(label).map(|lv| m.decode_fields(label, &lv.value)).unwrap_or_default())
     }
-    fn as_any(&self) -> &dyn std::any::Any { self }
 
     pub fn get_meter_id(&self) -> String {
-        if let Ok(lock) = self.inner.lock() { lock.get_meter_id() } else { String::new() }
+        self.with(|m| m.get_meter_id())
+    }
+
+    /// Alerts of the last frame, sorted by id
+    pub fn alerts(&self) -> Vec<Alert> {
+        let mut alerts = self.with(|m| m.alerts());
+        alerts.sort_by(|a, b| a.id.cmp(&b.id));
+        alerts
     }
 
-    pub fn get_all_discovery_messages(&self) -> Vec<(String, String)> {
-        if let Ok(lock) = self.inner.lock() { lock.get_all_discovery_messages() } else { Vec::new() }
+    pub fn labels(&self) -> Vec<String> {
+        self.with(|m| m.labels())
     }
 }
 
 pub trait TicMode {
     /// Return the mode name ("historique" or "standard")
     fn get_mode_name(&self) -> &'static str;
-    fn as_any(&self) -> &dyn std::any::Any;
+    /// All labels the mode may receive
     fn labels(&self) -> Vec<String> { Vec::new() }
+    fn label_values(&self) -> &HashMap<String, LabelValue>;
+    fn label_values_mut(&mut self) -> &mut HashMap<String, LabelValue>;
     fn handle_label_value(&mut self, _label: &str, _value: &str);
     fn set_meter_id(&mut self, id: &str);
     fn get_meter_id(&self) -> String;
     fn baudrate(&self) -> u32;
 
-    fn get_object_id(&self, label: &str) -> String {
-        let safe_label = sanitize_label(label);
-        format!("{}_{}", MQTT_ID_BASE, safe_label)
+    /// Store a group, with its horodate as timestamp
+    fn handle_group(&mut self, group: &Group) {
+        self.handle_label_value(&group.label, &sanitize_value(&group.value));
+        if let Some(lv) = self.label_values_mut().get_mut(&group.label) {
+            lv.timestamp = group.horodate.as_deref().and_then(horodate_to_iso);
+        }
     }
 
-    fn get_mqtt_config_topic(&self, label: &str) -> String {
-        let id = self.get_meter_id();
-        if id.is_empty() { return String::new(); }
-        format!("{}/{}/{}/config", MQTT_TOPIC_BASE, id, self.get_object_id(label))
+    /// Event labels hold only for the frame carrying them
+    fn handle_frame(&mut self, frame: &Frame) {
+        let events: Vec<String> = self.label_values().keys().filter(|l| self.is_event(l)).cloned().collect();
+        for label in events {
+            self.label_values_mut().remove(&label);
+        }
+        for group in &frame.groups {
+            self.handle_group(group);
+        }
     }
 
-    fn get_ha_device_class(&self, _label: &str) -> Option<&'static str> { None }
-    fn get_ha_state_class(&self, _label: &str) -> Option<&'static str> { None }
-    fn get_ha_unit(&self, _label: &str) -> Option<&'static str> { None }
+    /// Whether the value of a label is a number, labels with a unit by default
+    fn is_numeric(&self, label: &str) -> bool {
+        self.get_ha_unit(label).is_some()
+    }
 
-    fn get_all_discovery_messages(&self) -> Vec<(String, String)> {
-        let mut msgs = Vec::new();
-        let meter = self.get_meter_id();
-        if meter.is_empty() { return msgs; }
-        for label in self.labels() {
-            let safe_label = sanitize_label(&label);
-            let object_id = self.get_object_id(&label);
-            let config_topic = self.get_mqtt_config_topic(&label);
-            let state_topic = format!("{}/{}", MQTT_ID_BASE, meter);
-            let device_class = self.get_ha_device_class(&label);
-            let state_class = self.get_ha_state_class(&label);
-            let unit = self.get_ha_unit(&label);
-            // New: value_template for this label
-            let value_template = format!("{{{{ value_json['{}'] | default({{}}) | attr('raw') | default('') }}}}", safe_label);
-
-            let mut payload = format!(
-                "{{\"name\":\"TIC {}\",\"state_topic\":\"{}\",\"unique_id\":\"{}\",\"value_template\":\"{}\"",
-                safe_label, state_topic, object_id, value_template
-            );
-            // Device block
-            payload.push_str(&format!(
-                ",\"device\":{{\"identifiers\":[\"tic2mqtt_{}\"],\"manufacturer\":\"Enedis\",\"model\":\"TIC {} {}\",\"name\":\"Télé-information client {} {}\"}}",
-                object_id, object_id, self.get_mode_name(), object_id, self.get_mode_name()
-            ));
-            if let Some(dc) = device_class {
-                payload.push_str(&format!(",\"device_class\":\"{}\"", dc));
-            }
-            if let Some(sc) = state_class {
-                payload.push_str(&format!(",\"state_class\":\"{}\"", sc));
-            }
-            if let Some(u) = unit {
-                payload.push_str(&format!(",\"unit_of_measurement\":\"{}\"", u));
-            }
-            payload.push('}');
-            msgs.push((config_topic, payload));
+    fn decode_value(&self, label: &str, raw: &str) -> Value {
+        match raw.parse::<i64>() {
+            Ok(v) if self.is_numeric(label) => Value::Integer(v),
+            _ => Value::Text(raw.to_string()),
         }
-        msgs
     }
+
+    /// Fields decoded from the value of a label, e.g. the flags of a bitfield
+    fn decode_fields(&self, _label: &str, _raw: &str) -> Vec<(String, Value)> { Vec::new() }
+
+    /// Phase (1 to 3) a label of a three-phase meter is about
+    fn phase(&self, _label: &str) -> Option<u8> { None }
+
+    /// Whether a label is a warning sent only while its condition lasts, e.g. an overcurrent
+    fn is_event(&self, _label: &str) -> bool { false }
+
+    /// Indexes of the active energy drawn, in Wh, whose sum is the total consumption
+    fn energy_indexes(&self) -> Vec<&'static str> { Vec::new() }
+
+    /// Index of the tariff period in progress
+    fn active_index(&self) -> Option<String> { None }
+
+    /// Index of the active energy returned to the grid, in Wh
+    fn return_index(&self) -> Option<&'static str> { None }
+
+    /// Alerts raised by the current values
+    fn alerts(&self) -> Vec<Alert> { Vec::new() }
+
+    fn get_ha_device_class(&self, _label: &str) -> Option<&'static str> { None }
+    fn get_ha_state_class(&self, _label: &str) -> Option<&'static str> { None }
+    fn get_ha_unit(&self, _label: &str) -> Option<&'static str> { None }
+    /// Home Assistant entity category, "diagnostic" for settings of the meter contract
+    fn get_ha_entity_category(&self, _label: &str) -> Option<&'static str> { None }
 }
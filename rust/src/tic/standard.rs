@@ -1,8 +1,8 @@
-use std::any::Any;
-use crate::tic::TicMode;
+use crate::tic::{Alert, TicMode, Value};
 use std::collections::{HashSet, HashMap};
 
 use crate::tic::LabelValue;
+#[derive(Default)]
 pub struct StandardTIC {
     meter_id: String,
     pub label_values: HashMap<String, LabelValue>,
@@ -10,12 +10,11 @@ pub struct StandardTIC {
 
 impl StandardTIC {
     pub const BAUDRATE: u32 = 9600;
-    pub fn new() -> Self { StandardTIC { meter_id: String::new(), label_values: HashMap::new() } }
+    pub fn new() -> Self { Self::default() }
 }
 
 impl TicMode for StandardTIC {
     fn get_mode_name(&self) -> &'static str { "standard" }
-    fn as_any(&self) -> &dyn Any { self }
     fn baudrate(&self) -> u32 {
         Self::BAUDRATE
     }
@@ -34,9 +33,12 @@ impl TicMode for StandardTIC {
         labels.into_iter().map(|s| s.to_string()).collect()
     }
 
+    fn label_values(&self) -> &HashMap<String, LabelValue> { &self.label_values }
+    fn label_values_mut(&mut self) -> &mut HashMap<String, LabelValue> { &mut self.label_values }
+
     fn handle_label_value(&mut self, label: &str, value: &str) {
         if label == "ADSC" {
-            // Publish previous frame if any (handled in main.rs)
+            // Publish previous frame if any (handled by the caller)
             self.set_meter_id(value);
             // Do not clear label_values, just update values in place
         }
@@ -116,4 +118,51 @@ impl TicMode for StandardTIC {
         if varh.contains(label) { return Some("varh"); }
         None
     }
+
+    fn is_numeric(&self, label: &str) -> bool {
+        let unitless: HashSet<&str> = ["NTARF", "NJOURF", "NJOURF+1", "RELAIS", "VTIC", "PREF", "PCOUP"].into_iter().collect();
+        unitless.contains(label) || self.get_ha_unit(label).is_some()
+    }
+
+    /// STGE: bit 6 overvoltage on a phase, bit 7 reference power exceeded, bit 8 producer meter,
+    /// bit 9 active energy flowing to the grid
+    fn decode_fields(&self, label: &str, raw: &str) -> Vec<(String, Value)> {
+        match (label, u32::from_str_radix(raw, 16)) {
+            ("STGE", Ok(bits)) => vec![
+                ("overvoltage".to_string(), Value::Bool(bits & (1 << 6) != 0)),
+                ("overload".to_string(), Value::Bool(bits & (1 << 7) != 0)),
+                ("producer".to_string(), Value::Bool(bits & (1 << 8) != 0)),
+                ("injecting".to_string(), Value::Bool(bits & (1 << 9) != 0)),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    /// EAST is the total of the provider indexes (EASFxx)
+    fn energy_indexes(&self) -> Vec<&'static str> { vec!["EAST"] }
+
+    /// From the number of the tariff period in progress (NTARF), named by LTARF
+    fn active_index(&self) -> Option<String> {
+        let number: u8 = self.label_values.get("NTARF")?.value.parse().ok()?;
+        (1..=10).contains(&number).then(|| format!("EASF{:02}", number))
+    }
+
+    fn return_index(&self) -> Option<&'static str> { Some("EAIT") }
+
+    fn alerts(&self) -> Vec<Alert> {
+        let Some(stge) = self.label_values.get("STGE") else { return Vec::new() };
+        self.decode_fields("STGE", &stge.value)
+            .into_iter()
+            .filter(|(id, set)| ["overvoltage", "overload"].contains(&id.as_str()) && *set == Value::Bool(true))
+            .map(|(id, _)| Alert { id, label: "STGE".to_string(), value: stge.value.clone() })
+            .collect()
+    }
+
+    fn phase(&self, label: &str) -> Option<u8> {
+        let base = label.strip_suffix("-1").unwrap_or(label);
+        let per_phase = ["IRMS", "URMS", "UMOY", "SINSTS", "SMAXSN"];
+        let name = base.get(..base.len().checked_sub(1)?)?;
+        if !per_phase.contains(&name) { return None; }
+        base[name.len()..].parse().ok().filter(|p| (1..=3).contains(p))
+    }
 }
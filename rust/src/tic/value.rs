@@ -0,0 +1,45 @@
+use std::fmt;
+
+use crate::utils::json_escape;
+
+/// Decoded value of a label: an integer for indexes and measurements, a flag of a bitfield, text otherwise.
+/// Derived metrics such as costs may be fractional.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+}
+
+impl Value {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer(v) => Some(*v),
+            Value::Float(_) | Value::Bool(_) | Value::Text(_) => None,
+        }
+    }
+
+    /// JSON literal of the value: a number, a boolean or a string
+    pub fn to_json(&self) -> String {
+        match self {
+            Value::Integer(v) => v.to_string(),
+            // JSON has no NaN nor infinity
+            Value::Float(v) if v.is_finite() => v.to_string(),
+            Value::Float(_) => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Text(s) => format!("\"{}\"", json_escape(s)),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
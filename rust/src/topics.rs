@@ -0,0 +1,44 @@
+pub const MQTT_ID_BASE: &str = "tic2mqtt";
+pub const MQTT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// MQTT topic layout for state and Home Assistant discovery messages
+#[derive(Clone, Debug)]
+pub struct Topics {
+    /// Prefix of the state topics, e.g. "tic2mqtt"
+    pub prefix: String,
+    /// Home Assistant discovery prefix, e.g. "homeassistant"
+    pub discovery_prefix: String,
+}
+
+impl Default for Topics {
+    fn default() -> Self {
+        Topics { prefix: MQTT_ID_BASE.into(), discovery_prefix: MQTT_DISCOVERY_PREFIX.into() }
+    }
+}
+
+impl Topics {
+    /// State topic of a meter: "<prefix>/<meter>"
+    pub fn state(&self, meter: &str) -> String {
+        format!("{}/{}", self.prefix, meter)
+    }
+
+    /// Availability of a meter: "<prefix>/<meter>/status", "online" or "offline"
+    pub fn availability(&self, meter: &str) -> String {
+        format!("{}/{}/status", self.prefix, meter)
+    }
+
+    /// Alert events of a meter: "<prefix>/<meter>/event"
+    pub fn event(&self, meter: &str) -> String {
+        format!("{}/{}/event", self.prefix, meter)
+    }
+
+    /// Availability of the bridge itself: "<prefix>/status", also the MQTT last will
+    pub fn bridge_availability(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    /// Discovery config topic: "<discovery_prefix>/<component>/<meter>/<object_id>/config"
+    pub fn discovery(&self, component: &str, meter: &str, object_id: &str) -> String {
+        format!("{}/{}/{}/{}/config", self.discovery_prefix, component, meter, object_id)
+    }
+}
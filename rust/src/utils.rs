@@ -1,6 +1,4 @@
-
-pub const MQTT_ID_BASE: &str = "tic2mqtt";
-pub const MQTT_TOPIC_BASE: &str = "homeassistant/sensor/tic2mqtt/";
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn sanitize_ascii_printable(val: &str) -> String {
     val.chars().filter(|&c| (c as u32) >= 32 && (c as u32) <= 126).collect()
@@ -17,10 +15,104 @@ pub fn sanitize_value(value: &str) -> String {
     sanitize_ascii_printable(value)
 }
 
-/// Parse label and value from a line split by whitespace
-pub fn parse_label_value(line: &str) -> Option<(String, String)> {
-    let mut parts = line.split_whitespace();
-    let label = parts.next()?.to_string();
-    let value = parts.next()?.to_string();
-    Some((label, value))
+/// Escape a string for use inside a JSON string literal
+pub fn json_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Date (year, month, day) of a number of days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (if month <= 2 { era * 400 + yoe + 1 } else { era * 400 + yoe }, month, day)
+}
+
+/// Local date of a time, as days since 1970-01-01, the UTC date where the time zone is unknown
+pub fn local_day(time: SystemTime) -> i64 {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    #[cfg(unix)]
+    {
+        // SAFETY: localtime_r only writes the tm structure given
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let t = secs as libc::time_t;
+        if !unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+            return days_from_civil(tm.tm_year as i64 + 1900, tm.tm_mon as u32 + 1, tm.tm_mday as u32);
+        }
+    }
+    secs.div_euclid(86400)
+}
+
+/// Format a time as ISO 8601 UTC with milliseconds, e.g. "2025-01-01T12:00:00.000Z"
+pub fn format_timestamp(time: SystemTime) -> String {
+    let ms = time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0);
+    let secs = ms.div_euclid(1000);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let tod = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, tod / 3600, tod / 60 % 60, tod % 60, ms.rem_euclid(1000)
+    )
+}
+
+/// Parse a time formatted by `format_timestamp`, milliseconds are optional, or with an offset instead of Z
+/// as in the meter dates, e.g. "2025-01-15T13:00:00+01:00"
+pub fn parse_timestamp(value: &str) -> Option<SystemTime> {
+    let (value, offset) = match value.strip_suffix('Z') {
+        Some(value) => (value, 0),
+        None => {
+            let (value, offset) = value.split_at_checked(value.len().checked_sub(6)?)?;
+            let (sign, hm) = offset.split_at_checked(1)?;
+            let (h, m) = hm.split_once(':')?;
+            let minutes = h.parse::<u64>().ok()? * 60 + m.parse::<u64>().ok()?;
+            match sign {
+                "+" => (value, minutes as i64 * 60),
+                "-" => (value, -(minutes as i64) * 60),
+                _ => return None,
+            }
+        }
+    };
+    let (date, time) = value.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (hms, millis) = match time.split_once('.') {
+        Some((hms, ms)) if ms.len() == 3 => (hms, ms.parse::<u64>().ok()?),
+        Some(_) => return None,
+        None => (time, 0),
+    };
+    let mut hms = hms.splitn(3, ':').map(|p| p.parse::<u64>().ok());
+    let (h, m, s) = (hms.next()??, hms.next()??, hms.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || h > 23 || m > 59 || s > 60 {
+        return None;
+    }
+    let secs = days_from_civil(year, month as u32, day as u32) * 86400 + (h * 3600 + m * 60 + s) as i64 - offset;
+    Some(UNIX_EPOCH + Duration::from_millis(u64::try_from(secs).ok()? * 1000 + millis))
 }
@@ -0,0 +1,305 @@
+//! Helpers of the integration tests: an in-process MQTT broker stand-in, and a runner of the tic2mqtt binary.
+
+#![allow(dead_code)]
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A message published to the broker, or a last will
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// MQTT packets received by the broker
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    Connect { client_id: String, username: Option<String>, password: Option<String>, will: Option<Message> },
+    Publish(Message),
+    Subscribe(Vec<String>),
+    Disconnect,
+}
+
+/// MQTT 3.1.1 broker stand-in: acknowledges everything, records the packets received, and sends the retained
+/// messages set with `retain` to the clients subscribing to their topic
+pub struct Broker {
+    pub port: u16,
+    packets: Arc<Mutex<Vec<Packet>>>,
+    retained: Arc<Mutex<Vec<Message>>>,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Broker {
+    pub fn start() -> Broker {
+        Broker::start_on("127.0.0.1:0")
+    }
+
+    /// Broker listening on a given address, e.g. one a running tic2mqtt already tries to connect to
+    pub fn start_on(addr: &str) -> Broker {
+        let listener = TcpListener::bind(addr).expect("bind broker");
+        let port = listener.local_addr().unwrap().port();
+        let packets = Arc::new(Mutex::new(Vec::new()));
+        let retained = Arc::new(Mutex::new(Vec::new()));
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let (recorded, kept, connected) = (packets.clone(), retained.clone(), clients.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(clone) = stream.try_clone() {
+                    connected.lock().unwrap().push(clone);
+                }
+                let (recorded, kept) = (recorded.clone(), kept.clone());
+                std::thread::spawn(move || {
+                    let _ = serve(stream, &recorded, &kept);
+                });
+            }
+        });
+        Broker { port, packets, retained, clients }
+    }
+
+    /// Close the connections of the clients, as a broker restarting would
+    pub fn drop_clients(&self) {
+        for stream in self.clients.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    /// Retain a message, sent to the clients subscribing to its exact topic from now on
+    pub fn retain(&self, topic: &str, payload: &str) {
+        let message = Message { topic: topic.to_string(), payload: payload.to_string(), qos: 0, retain: true };
+        self.retained.lock().unwrap().push(message);
+    }
+
+    pub fn url(&self) -> String {
+        format!("tcp://127.0.0.1:{}", self.port)
+    }
+
+    pub fn packets(&self) -> Vec<Packet> {
+        self.packets.lock().unwrap().clone()
+    }
+
+    pub fn publishes(&self) -> Vec<Message> {
+        self.packets().into_iter().filter_map(|p| match p { Packet::Publish(m) => Some(m), _ => None }).collect()
+    }
+
+    /// Messages published to a topic, in order
+    pub fn published_to(&self, topic: &str) -> Vec<Message> {
+        self.publishes().into_iter().filter(|m| m.topic == topic).collect()
+    }
+
+    /// Payloads published to a topic, in order
+    pub fn payloads(&self, topic: &str) -> Vec<String> {
+        self.published_to(topic).into_iter().map(|m| m.payload).collect()
+    }
+
+    /// Wait until `f` holds on the packets received
+    pub fn wait_for(&self, timeout: Duration, f: impl Fn(&[Packet]) -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if f(&self.packets()) { return true; }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+}
+
+fn read_string(data: &[u8], i: &mut usize) -> io::Result<String> {
+    let bytes = read_bytes(data, i)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_bytes(data: &[u8], i: &mut usize) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated packet");
+    let len = u16::from_be_bytes([*data.get(*i).ok_or_else(invalid)?, *data.get(*i + 1).ok_or_else(invalid)?]) as usize;
+    let bytes = data.get(*i + 2..*i + 2 + len).ok_or_else(invalid)?.to_vec();
+    *i += 2 + len;
+    Ok(bytes)
+}
+
+fn serve(mut stream: TcpStream, packets: &Mutex<Vec<Packet>>, retained: &Mutex<Vec<Message>>) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; 1];
+        stream.read_exact(&mut header)?;
+        // Remaining length, variable length encoding
+        let (mut len, mut shift) = (0usize, 0);
+        loop {
+            let mut b = [0u8; 1];
+            stream.read_exact(&mut b)?;
+            len |= ((b[0] & 0x7F) as usize) << shift;
+            shift += 7;
+            if b[0] & 0x80 == 0 { break; }
+        }
+        let mut data = vec![0u8; len];
+        stream.read_exact(&mut data)?;
+        let mut i = 0;
+        match header[0] >> 4 {
+            1 => {
+                read_string(&data, &mut i)?; // protocol name
+                let flags = data[i + 1];
+                i += 4; // level, flags, keep alive
+                let client_id = read_string(&data, &mut i)?;
+                let will = if flags & 0x04 != 0 {
+                    let topic = read_string(&data, &mut i)?;
+                    let payload = read_string(&data, &mut i)?;
+                    Some(Message { topic, payload, qos: (flags >> 3) & 3, retain: flags & 0x20 != 0 })
+                } else {
+                    None
+                };
+                let username = if flags & 0x80 != 0 { Some(read_string(&data, &mut i)?) } else { None };
+                let password = if flags & 0x40 != 0 { Some(read_string(&data, &mut i)?) } else { None };
+                packets.lock().unwrap().push(Packet::Connect { client_id, username, password, will });
+                stream.write_all(&[0x20, 2, 0, 0])?;
+            }
+            3 => {
+                let qos = (header[0] >> 1) & 3;
+                let topic = read_string(&data, &mut i)?;
+                let id = if qos > 0 { let id = [data[i], data[i + 1]]; i += 2; Some(id) } else { None };
+                let payload = String::from_utf8_lossy(&data[i..]).into_owned();
+                packets.lock().unwrap().push(Packet::Publish(Message { topic, payload, qos, retain: header[0] & 1 != 0 }));
+                match (qos, id) {
+                    (1, Some(id)) => stream.write_all(&[0x40, 2, id[0], id[1]])?,
+                    (2, Some(id)) => stream.write_all(&[0x50, 2, id[0], id[1]])?,
+                    _ => {}
+                }
+            }
+            6 => stream.write_all(&[0x70, 2, data[0], data[1]])?,
+            8 => {
+                let id = [data[0], data[1]];
+                i = 2;
+                let mut topics = Vec::new();
+                while i < data.len() {
+                    topics.push(read_string(&data, &mut i)?);
+                    i += 1; // requested QoS
+                }
+                let mut ack = vec![0x90, 2 + topics.len() as u8, id[0], id[1]];
+                ack.extend(topics.iter().map(|_| 1u8));
+                stream.write_all(&ack)?;
+                let messages: Vec<Message> = retained.lock().unwrap().iter().filter(|m| topics.contains(&m.topic)).cloned().collect();
+                for m in messages {
+                    // PUBLISH, QoS 0, retained: topic and payload, short enough for a one byte length
+                    let mut publish = vec![0x31, (2 + m.topic.len() + m.payload.len()) as u8];
+                    publish.extend((m.topic.len() as u16).to_be_bytes());
+                    publish.extend(m.topic.as_bytes());
+                    publish.extend(m.payload.as_bytes());
+                    stream.write_all(&publish)?;
+                }
+                packets.lock().unwrap().push(Packet::Subscribe(topics));
+            }
+            12 => stream.write_all(&[0xD0, 0])?,
+            14 => {
+                packets.lock().unwrap().push(Packet::Disconnect);
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Path of a test fixture
+pub fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Run tic2mqtt to completion, without the environment of the test, killed after 30 s
+pub fn tic2mqtt(args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tic2mqtt"))
+        .args(args)
+        .env_clear()
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run tic2mqtt");
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            let output = child.wait_with_output().unwrap();
+            panic!("tic2mqtt timed out: {}", String::from_utf8_lossy(&output.stderr));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    child.wait_with_output().unwrap()
+}
+
+/// Run tic2mqtt replaying a fixture to the broker, and check it succeeded
+pub fn replay(broker: &Broker, fixture_name: &str, mode: &str, extra: &[&str]) -> Output {
+    let path = fixture(fixture_name);
+    let url = broker.url();
+    let mut args = vec!["--replay", &path, "--mode", mode, "--mqtt-server", &url];
+    args.extend_from_slice(extra);
+    let output = tic2mqtt(&args);
+    assert!(output.status.success(), "tic2mqtt failed: {}", String::from_utf8_lossy(&output.stderr));
+    // The broker may still be reading the last packets
+    assert!(broker.wait_for(Duration::from_secs(5), |p| p.contains(&Packet::Disconnect)), "no disconnect");
+    output
+}
+
+/// A tic2mqtt process reading a fixture from its stdin, kept running until `finish` closes it (or it is dropped)
+pub struct Running {
+    child: std::process::Child,
+}
+
+impl Running {
+    /// Start tic2mqtt on stdin and feed it the fixture
+    pub fn start(fixture_name: &str, args: &[&str]) -> Running {
+        let mut running = Running::spawn(args);
+        running.feed(fixture_name);
+        running
+    }
+
+    /// Start tic2mqtt on stdin, without input yet
+    pub fn spawn(args: &[&str]) -> Running {
+        let child = Command::new(env!("CARGO_BIN_EXE_tic2mqtt"))
+            .args(["--serial", "-"])
+            .args(args)
+            .env_clear()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("run tic2mqtt");
+        Running { child }
+    }
+
+    /// Write the fixture to stdin
+    pub fn feed(&mut self, fixture_name: &str) {
+        let data = std::fs::read(fixture(fixture_name)).unwrap();
+        self.child.stdin.as_mut().unwrap().write_all(&data).unwrap();
+    }
+
+    /// Close stdin, and wait for the end of the process
+    pub fn finish(mut self) -> Output {
+        drop(self.child.stdin.take());
+        self.child.wait_with_output().unwrap()
+    }
+}
+
+/// A local address with a free port
+pub fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// GET `path` from `addr`, retrying while the server starts: (status, body)
+pub fn http_get(addr: &str, path: &str) -> (u16, String) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(e) if Instant::now() > deadline => panic!("cannot connect to {}: {}", addr, e),
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").expect("HTTP response");
+    let status = head.split_whitespace().nth(1).and_then(|s| s.parse().ok()).expect("HTTP status");
+    (status, body.to_string())
+}
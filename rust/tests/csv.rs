@@ -0,0 +1,86 @@
+//! End-to-end tests of the CSV output.
+
+mod common;
+
+use common::{fixture, tic2mqtt};
+
+/// Empty temporary directory of a test
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("tic2mqtt-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Files of a directory: (name, content)
+fn files(dir: &std::path::Path) -> Vec<(String, String)> {
+    let mut files: Vec<_> = std::fs::read_dir(dir).unwrap()
+        .map(|e| e.unwrap())
+        .map(|e| (e.file_name().to_string_lossy().into_owned(), std::fs::read_to_string(e.path()).unwrap()))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn one_row_per_frame() {
+    let dir = temp_dir("csv");
+    let dir_arg = dir.display().to_string();
+    let output = tic2mqtt(&["--replay", &fixture("standard.tic"), "--mode", "standard", "--meter-id", "linky", "--outputs", "csv", "--csv-dir", &dir_arg]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let files = files(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_eq!(files.len(), 1, "{:?}", files);
+    let (name, content) = &files[0];
+    assert!(name.starts_with("linky-") && name.ends_with(".csv") && name.len() == "linky-2025-01-15.csv".len(), "{}", name);
+
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", content);
+    // Every label of the mode, in order, then the derived metrics
+    assert!(lines[0].starts_with("timestamp,date,ADSC,CCAIN,CCAIN-1,CCASN,CCASN-1,DPM1,"), "{}", lines[0]);
+    assert!(lines[0].ends_with(",UMOY3,URMS1,URMS2,URMS3,VTIC,active_power,TOTAL"), "{}", lines[0]);
+    let header: Vec<&str> = lines[0].split(',').collect();
+    for line in &lines[1..] {
+        let row: Vec<&str> = line.split(',').collect();
+        assert_eq!(row.len(), header.len(), "{}", line);
+        let column = |name: &str| row[header.iter().position(|h| *h == name).unwrap()];
+        assert_eq!(column("date"), "2025-01-15T13:00:00+01:00");
+        assert_eq!(column("ADSC"), "021728123456");
+        assert_eq!(column("LTARF"), "HP BLEU");
+        assert_eq!(column("EAST"), "19636616");
+        assert_eq!(column("TOTAL"), "19636616");
+        // Not sent by this meter
+        assert_eq!(column("SINSTI"), "");
+    }
+    assert!(lines[2].contains(",1528,"), "{}", lines[2]);
+}
+
+#[test]
+fn one_row_per_interval() {
+    let dir = temp_dir("csv-interval");
+    let dir_arg = dir.display().to_string();
+    let output = tic2mqtt(&["--replay", &fixture("historique.tic"), "--mode", "historique", "--outputs", "csv", "--csv-dir", &dir_arg, "--csv-interval", "3600"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let files = files(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_eq!(files.len(), 1, "{:?}", files);
+    let (name, content) = &files[0];
+    assert!(name.starts_with("031762001234-"), "{}", name);
+    // The first of the 3 frames
+    assert_eq!(content.lines().count(), 2, "{}", content);
+    assert!(content.lines().nth(1).unwrap().contains(",HP..,"), "{}", content);
+}
+
+#[test]
+fn invalid_csv_settings_are_rejected() {
+    let output = tic2mqtt(&["--serial", "-", "--csv-dir", "/nonexistent/tic2mqtt"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--csv-dir"));
+
+    let dir = std::env::temp_dir().display().to_string();
+    let output = tic2mqtt(&["--serial", "-", "--csv-dir", &dir, "--csv-interval", "0.5"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--csv-interval"));
+}
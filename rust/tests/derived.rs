@@ -0,0 +1,244 @@
+//! Tests of the metrics derived from successive frames.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use tic2mqtt::derived::{Derived, Metric, ProductionIndex, Tariff};
+use tic2mqtt::tic::frame::{Frame, Group};
+use tic2mqtt::tic::{TicModeEnum, TicModeHandle, Value};
+
+/// Feed a frame of (label, value) groups received at `start + seconds`
+fn feed(meter: &TicModeHandle, derived: &mut Derived, start: Instant, seconds: u64, groups: &[(&str, &str)]) -> Vec<Metric> {
+    let frame = Frame { groups: groups.iter().map(|(l, v)| Group { label: l.to_string(), value: v.to_string(), horodate: None }).collect() };
+    meter.handle_frame(&frame);
+    meter.with(|m| derived.update(m, start + Duration::from_secs(seconds), SystemTime::now()));
+    derived.metrics()
+}
+
+/// Value of a metric, if computed
+fn metric(metrics: &[Metric], id: &str) -> Option<Value> {
+    metrics.iter().find(|m| m.id == id).map(|m| m.value.clone())
+}
+
+fn power(metrics: Vec<Metric>) -> Option<i64> {
+    metric(&metrics, "active_power").and_then(|v| v.as_i64())
+}
+
+#[test]
+fn active_power_across_tariff_periods() {
+    let meter = TicModeHandle::new(TicModeEnum::Historique);
+    let mut derived = Derived::new().with_power_window(Duration::from_secs(60));
+    let start = Instant::now();
+    assert_eq!(derived.ids(), ["active_power", "TOTAL"]);
+
+    // No estimate until the samples span the window
+    assert_eq!(power(feed(&meter, &mut derived, start, 0, &[("HCHC", "005101233"), ("HCHP", "005641450"), ("PTEC", "HP..")])), None);
+    assert_eq!(power(feed(&meter, &mut derived, start, 30, &[("HCHC", "005101233"), ("HCHP", "005641463"), ("PTEC", "HP..")])), None);
+    // 25 Wh in 60 s
+    assert_eq!(power(feed(&meter, &mut derived, start, 60, &[("HCHC", "005101233"), ("HCHP", "005641475"), ("PTEC", "HP..")])), Some(1500));
+    // Switch to off-peak hours: the HCHC index moves on
+    assert_eq!(power(feed(&meter, &mut derived, start, 90, &[("HCHC", "005101245"), ("HCHP", "005641475"), ("PTEC", "HC..")])), Some(1440));
+    assert_eq!(power(feed(&meter, &mut derived, start, 120, &[("HCHC", "005101283"), ("HCHP", "005641475"), ("PTEC", "HC..")])), Some(3000));
+}
+
+#[test]
+fn active_power_with_index_rollover() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new().with_power_window(Duration::from_secs(10));
+    let start = Instant::now();
+    feed(&meter, &mut derived, start, 0, &[("EAST", "999999990")]);
+    assert_eq!(power(feed(&meter, &mut derived, start, 10, &[("EAST", "000000010")])), Some(7200));
+    // An index going backwards restarts the estimate
+    assert_eq!(power(feed(&meter, &mut derived, start, 20, &[("EAST", "000000005")])), None);
+    assert_eq!(power(feed(&meter, &mut derived, start, 30, &[("EAST", "000000015")])), Some(3600));
+}
+
+#[test]
+fn active_power_disabled() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new().with_power_window(Duration::ZERO);
+    let start = Instant::now();
+    assert_eq!(derived.ids(), ["TOTAL"]);
+    feed(&meter, &mut derived, start, 0, &[("EAST", "000000010")]);
+    assert_eq!(power(feed(&meter, &mut derived, start, 60, &[("EAST", "000000020")])), None);
+}
+
+#[test]
+fn total_of_the_tariff_indexes() {
+    let meter = TicModeHandle::new(TicModeEnum::Historique);
+    let mut derived = Derived::new();
+    let start = Instant::now();
+    // Unknown tariff option: every index is expected
+    assert_eq!(metric(&feed(&meter, &mut derived, start, 0, &[("HCHC", "005101233"), ("HCHP", "005641450")]), "TOTAL"), None);
+    let metrics = feed(&meter, &mut derived, start, 1, &[("OPTARIF", "HC.."), ("HCHC", "005101233"), ("HCHP", "005641450")]);
+    assert_eq!(metric(&metrics, "TOTAL"), Some(Value::Integer(10742683)));
+
+    let meter = TicModeHandle::new(TicModeEnum::Historique);
+    let mut derived = Derived::new();
+    let tempo = [("OPTARIF", "BBR("), ("BBRHCJB", "000000100"), ("BBRHPJB", "000000200"), ("BBRHCJW", "000000010"), ("BBRHPJW", "000000020"), ("BBRHCJR", "000000001")];
+    // An index missing from the first frame
+    assert_eq!(metric(&feed(&meter, &mut derived, start, 0, &tempo), "TOTAL"), None);
+    let metrics = feed(&meter, &mut derived, start, 1, &[("BBRHPJR", "000000002")]);
+    assert_eq!(metric(&metrics, "TOTAL"), Some(Value::Integer(333)));
+
+    // Standard: the total of the provider indexes
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new();
+    let metrics = feed(&meter, &mut derived, start, 0, &[("EAST", "019636616"), ("EASF01", "005101233")]);
+    assert_eq!(metric(&metrics, "TOTAL"), Some(Value::Integer(19636616)));
+}
+
+/// Feed a standard frame at a meter date, e.g. "H250130120000"
+fn feed_on(meter: &TicModeHandle, derived: &mut Derived, date: &str, groups: &[(&str, &str)]) -> Vec<Metric> {
+    let date = Group { label: "DATE".to_string(), value: String::new(), horodate: Some(date.to_string()) };
+    let groups = groups.iter().map(|(l, v)| Group { label: l.to_string(), value: v.to_string(), horodate: None });
+    let frame = Frame { groups: std::iter::once(date).chain(groups).collect() };
+    meter.handle_frame(&frame);
+    meter.with(|m| derived.update(m, Instant::now(), SystemTime::now()));
+    derived.metrics()
+}
+
+fn counter(metrics: &[Metric], id: &str) -> Option<i64> {
+    metric(metrics, id).and_then(|v| v.as_i64())
+}
+
+#[test]
+fn consumption_counters_follow_the_meter_date() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new();
+    // Thursday 2025-01-30
+    let metrics = feed_on(&meter, &mut derived, "H250130080000", &[("EAST", "000001000")]);
+    assert_eq!(counter(&metrics, "EAST_today"), Some(0));
+    assert_eq!(counter(&metrics, "EAST_yesterday"), None);
+    assert!(derived.counters_changed());
+    let metrics = feed_on(&meter, &mut derived, "H250130230000", &[("EAST", "000001500")]);
+    assert_eq!(counter(&metrics, "EAST_today"), Some(500));
+    assert_eq!(counter(&metrics, "TOTAL_today"), Some(500));
+    assert!(!derived.counters_changed());
+
+    // The first frame of the next day closes the previous one
+    let metrics = feed_on(&meter, &mut derived, "H250131080000", &[("EAST", "000001800")]);
+    assert_eq!(counter(&metrics, "EAST_today"), Some(0));
+    assert_eq!(counter(&metrics, "EAST_yesterday"), Some(800));
+    assert_eq!(counter(&metrics, "EAST_this_week"), Some(800));
+    assert!(derived.counters_changed());
+
+    // New month on Saturday
+    let metrics = feed_on(&meter, &mut derived, "H250201080000", &[("EAST", "000002000")]);
+    assert_eq!(counter(&metrics, "EAST_this_month"), Some(0));
+    assert_eq!(counter(&metrics, "EAST_last_month"), Some(1000));
+    assert_eq!(counter(&metrics, "TOTAL_last_month"), Some(1000));
+    assert_eq!(counter(&metrics, "EAST_this_week"), Some(1000));
+
+    // Monday, after a day without frames: yesterday is unknown
+    let metrics = feed_on(&meter, &mut derived, "H250203080000", &[("EAST", "000002300")]);
+    assert_eq!(counter(&metrics, "EAST_yesterday"), None);
+    assert_eq!(counter(&metrics, "EAST_last_week"), Some(1300));
+    assert_eq!(counter(&metrics, "EAST_this_week"), Some(0));
+    assert_eq!(counter(&metrics, "EAST_this_month"), Some(300));
+}
+
+#[test]
+fn consumption_counters_are_restored() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new();
+    feed_on(&meter, &mut derived, "E250615080000", &[("EAST", "999999900")]);
+    let saved = derived.counters().clone();
+
+    // A restart on the same day, after the index wrapped around
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new();
+    derived.set_counters(saved);
+    let metrics = feed_on(&meter, &mut derived, "E250615200000", &[("EAST", "000000100")]);
+    assert_eq!(counter(&metrics, "EAST_today"), Some(200));
+    assert!(!derived.counters_changed());
+}
+
+#[test]
+fn consumption_counters_restart_when_the_index_goes_back() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new();
+    feed_on(&meter, &mut derived, "H250130080000", &[("EAST", "000005000")]);
+    assert!(derived.counters_changed());
+    feed_on(&meter, &mut derived, "H250130090000", &[("EAST", "000005400")]);
+    assert!(!derived.counters_changed());
+
+    // A replaced meter starts again from a lower index, not a wrap around
+    let metrics = feed_on(&meter, &mut derived, "H250130100000", &[("EAST", "000000100")]);
+    assert_eq!(counter(&metrics, "EAST_today"), Some(0));
+    assert_eq!(counter(&metrics, "EAST_this_month"), Some(0));
+    assert!(derived.counters_changed());
+    let metrics = feed_on(&meter, &mut derived, "H250130110000", &[("EAST", "000000350")]);
+    assert_eq!(counter(&metrics, "EAST_today"), Some(250));
+
+    // Nor does the next day get the consumption of the other meter
+    let metrics = feed_on(&meter, &mut derived, "H250131080000", &[("EAST", "000000400")]);
+    assert_eq!(counter(&metrics, "EAST_yesterday"), Some(300));
+}
+
+#[test]
+fn costs_of_the_tariff_periods() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let prices = [("EASF01", 0.2), ("EASF02", 0.3), ("EAIT", 0.1)].map(|(l, p)| (l.to_string(), p)).into_iter().collect();
+    let mut derived = Derived::new().with_tariff(Tariff { prices, subscription: 31.0 });
+    assert_eq!(derived.ids(), ["TOTAL", "rate", "cost_today", "cost_this_month"]);
+
+    // The subscription only, spread over the 31 days of January: the 15th day, and the 15 days so far
+    let metrics = feed_on(&meter, &mut derived, "H250115080000", &[("EAST", "000001000"), ("EASF01", "000000400"), ("EASF02", "000000600"), ("EAIT", "000000000"), ("NTARF", "01")]);
+    assert_eq!(metric(&metrics, "rate"), Some(Value::Float(0.2)));
+    assert_eq!(metric(&metrics, "cost_today"), Some(Value::Float(1.0)));
+    assert_eq!(metric(&metrics, "cost_this_month"), Some(Value::Float(15.0)));
+    assert_eq!(counter(&metrics, "EASF01_today"), Some(0));
+
+    // 1 kWh at 0.2, 2 kWh at 0.3, and 0.5 kWh returned at 0.1
+    let metrics = feed_on(&meter, &mut derived, "H250115200000", &[("EAST", "000004000"), ("EASF01", "000001400"), ("EASF02", "000002600"), ("EAIT", "000000500"), ("NTARF", "02")]);
+    assert_eq!(metric(&metrics, "rate"), Some(Value::Float(0.3)));
+    assert_eq!(metric(&metrics, "cost_today"), Some(Value::Float(1.75)));
+    assert_eq!(metric(&metrics, "cost_this_month"), Some(Value::Float(15.75)));
+    // Only the energy indexes are summed into the total
+    assert_eq!(counter(&metrics, "TOTAL_today"), Some(3000));
+}
+
+#[test]
+fn producer_returning_to_the_grid() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut derived = Derived::new().with_power_window(Duration::from_secs(60));
+    let start = Instant::now();
+    // Producer meter (bit 8), returning to the grid (bit 9)
+    feed(&meter, &mut derived, start, 0, &[("DATE", ""), ("EAST", "000010000"), ("EAIT", "000002000"), ("STGE", "00000300")]);
+    assert_eq!(meter.decoded_fields("STGE")[2..], [("producer".to_string(), Value::Bool(true)), ("injecting".to_string(), Value::Bool(true))]);
+    assert!(meter.alerts().is_empty());
+
+    // 5 Wh drawn and 30 Wh returned in 60 s
+    let metrics = feed(&meter, &mut derived, start, 60, &[("EAST", "000010005"), ("EAIT", "000002030")]);
+    assert_eq!(power(metrics.clone()), Some(300));
+    assert_eq!(metric(&metrics, "return_power"), Some(Value::Integer(1800)));
+    assert_eq!(metric(&metrics, "net_power"), Some(Value::Integer(-1500)));
+    assert_eq!(metric(&metrics, "EAIT_today"), Some(Value::Integer(30)));
+    assert_eq!(metric(&metrics, "NET_today"), Some(Value::Integer(-25)));
+    // The returned energy is not part of the total
+    assert_eq!(metric(&metrics, "TOTAL_today"), Some(Value::Integer(5)));
+}
+
+#[test]
+fn self_consumption_of_a_producer() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let production = Arc::new(ProductionIndex::default());
+    let mut derived = Derived::new().with_production(production.clone());
+    // Nothing counted until the production is known
+    let metrics = feed_on(&meter, &mut derived, "H250115080000", &[("EAST", "000010000"), ("EAIT", "000002000")]);
+    assert_eq!(counter(&metrics, "PRODUCTION_today"), None);
+    assert_eq!(counter(&metrics, "SELF_today"), None);
+
+    production.set(500_000);
+    feed_on(&meter, &mut derived, "H250115090000", &[("EAST", "000010000"), ("EAIT", "000002000")]);
+    // 1200 Wh produced, 300 Wh of them returned
+    production.set(501_200);
+    let metrics = feed_on(&meter, &mut derived, "H250115120000", &[("EAST", "000010000"), ("EAIT", "000002300")]);
+    assert_eq!(counter(&metrics, "PRODUCTION_today"), Some(1200));
+    assert_eq!(counter(&metrics, "SELF_today"), Some(900));
+    assert_eq!(counter(&metrics, "SELF_this_month"), Some(900));
+    // The production index lagging behind the meter
+    let metrics = feed_on(&meter, &mut derived, "H250115123000", &[("EAST", "000010000"), ("EAIT", "000003500")]);
+    assert_eq!(counter(&metrics, "SELF_today"), Some(0));
+}
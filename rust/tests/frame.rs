@@ -0,0 +1,97 @@
+//! Tests of the TIC frame parser of the library.
+
+use tic2mqtt::tic::frame::{FrameDecoder, FrameError, Group, checksum, format_group, horodate_to_iso, parse_group};
+use tic2mqtt::tic::{TicModeEnum, TicModeHandle, Value};
+
+fn group(label: &str, value: &str, horodate: Option<&str>) -> Group {
+    Group { label: label.to_string(), value: value.to_string(), horodate: horodate.map(String::from) }
+}
+
+#[test]
+fn historique_groups() {
+    assert_eq!(checksum("ADCO 031762001234"), '4');
+    assert_eq!(parse_group("ADCO 031762001234 4\r"), Ok(group("ADCO", "031762001234", None)));
+    // The checksum can itself be a space
+    assert_eq!(parse_group("PTEC HP..  "), Ok(group("PTEC", "HP..", None)));
+    assert_eq!(
+        parse_group("PAPP 01150 )"),
+        Err(FrameError::Checksum { line: "PAPP 01150 )".to_string(), expected: '(', found: ')' })
+    );
+    assert_eq!(parse_group("PAPP"), Err(FrameError::Malformed("PAPP".to_string())));
+}
+
+#[test]
+fn standard_groups() {
+    assert_eq!(parse_group("EAST\t002404175\t&"), Ok(group("EAST", "002404175", None)));
+    assert_eq!(parse_group("DATE\tE250701140000\t\t2"), Ok(group("DATE", "", Some("E250701140000"))));
+    assert_eq!(parse_group("NGTF\t      BASE      \t<"), Ok(group("NGTF", "      BASE      ", None)));
+    assert!(matches!(parse_group("EAST\t002404176\t&"), Err(FrameError::Checksum { .. })));
+}
+
+#[test]
+fn formatted_groups_parse_back() {
+    for (g, standard) in [
+        (group("HCHC", "012345678", None), false),
+        (group("SMAXSN", "01500", Some("H250115130000")), true),
+        (group("MSG1", "PAS DE          MESSAGE         ", None), true),
+    ] {
+        let line = format_group(&g, standard);
+        assert_eq!(parse_group(&line), Ok(g), "{:?}", line);
+    }
+}
+
+#[test]
+fn horodates() {
+    assert_eq!(horodate_to_iso("E250701140000").as_deref(), Some("2025-07-01T14:00:00+02:00"));
+    assert_eq!(horodate_to_iso("H250115130000").as_deref(), Some("2025-01-15T13:00:00+01:00"));
+    assert_eq!(horodate_to_iso("h250115130000").as_deref(), Some("2025-01-15T13:00:00+01:00"));
+    assert_eq!(horodate_to_iso(" 250115130000").as_deref(), Some("2025-01-15T13:00:00"));
+    assert_eq!(horodate_to_iso("H2501151300"), None);
+}
+
+#[test]
+fn frames_between_stx_and_etx() {
+    let mut decoder = FrameDecoder::new();
+    // A partial frame before the first STX is dropped
+    assert!(decoder.push_line("PAPP 01150 (\r\x03\x02").is_empty());
+    assert!(decoder.push_line("ADCO 031762001234 4\r").is_empty());
+    let frames = decoder.push_line("PAPP 01150 (\r\x03\x02");
+    assert_eq!(frames.len(), 1);
+    let frame = frames[0].as_ref().unwrap();
+    assert_eq!(frame.value("ADCO"), Some("031762001234"));
+    assert_eq!(frame.value("PAPP"), Some("01150"));
+    // An interrupted frame (EOT) is dropped
+    decoder.push_line("ADCO 031762001234 4\r\x04");
+    assert!(decoder.push_line("\x03").is_empty());
+    assert_eq!(decoder.finish(), None);
+}
+
+#[test]
+fn frames_without_delimiters() {
+    let mut decoder = FrameDecoder::new();
+    assert!(decoder.push_line("ADCO 031762001234 4").is_empty());
+    assert!(decoder.push_line("PAPP 01150 (").is_empty());
+    let frames = decoder.push_line("ADCO 031762001234 4");
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].as_ref().unwrap().groups.len(), 2);
+    // The last frame is complete at the end of the stream
+    let last = decoder.finish().unwrap();
+    assert_eq!(last.groups, [group("ADCO", "031762001234", None)]);
+}
+
+#[test]
+fn decoded_values() {
+    let meter = TicModeHandle::new(TicModeEnum::Standard);
+    let mut decoder = FrameDecoder::new();
+    for line in ["\x02", "ADSC\t021728123456\t6\r", "EAST\t002404175\t&\r", "DATE\tE250701140000\t\t2\r", "NTARF\t01\tN\r\x03"] {
+        for frame in decoder.push_line(line) {
+            meter.handle_frame(&frame.unwrap());
+        }
+    }
+    assert_eq!(meter.get_meter_id(), "021728123456");
+    assert_eq!(meter.decoded_value("EAST"), Some(Value::Integer(2404175)));
+    assert_eq!(meter.decoded_value("NTARF"), Some(Value::Integer(1)));
+    // The meter address is not a number, despite its digits
+    assert_eq!(meter.decoded_value("ADSC"), Some(Value::Text("021728123456".to_string())));
+    assert_eq!(meter.get_label_values()["DATE"].timestamp.as_deref(), Some("2025-07-01T14:00:00+02:00"));
+}
@@ -0,0 +1,314 @@
+//! End-to-end tests of the HTTP endpoints, on a running tic2mqtt.
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use common::{Broker, Running, free_addr, http_get, tic2mqtt};
+
+/// GET `path` until `predicate` holds on the body
+fn get_until(addr: &str, path: &str, predicate: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let (status, body) = http_get(addr, path);
+        assert_eq!(status, 200);
+        if predicate(&body) { return body; }
+        assert!(Instant::now() < deadline, "timed out, last body:\n{}", body);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn prometheus_metrics() {
+    let broker = Broker::start();
+    let addr = free_addr();
+    let url = broker.url();
+    let running = Running::start("historique.tic", &["--mode", "historique", "--mqtt-server", &url, "--metrics-listen", &addr]);
+
+    // The last frame of the fixture ends with the input
+    let body = get_until(&addr, "/metrics", |b| b.contains(r#"tic2mqtt_frames_total{meter="031762001234"} 2"#) && b.contains("tic2mqtt_mqtt_connects_total 1"));
+    for expected in [
+        "# TYPE tic_value gauge",
+        r#"tic_value{meter="031762001234",label="PAPP",unit="VA"} 1351"#,
+        "# TYPE tic_value_total counter",
+        r#"tic_value_total{meter="031762001234",label="HCHC",unit="Wh"} 5101233"#,
+        r#"tic_value_total{meter="031762001234",label="TOTAL",unit="Wh"} 10742683"#,
+        r#"tic2mqtt_checksum_errors_total{meter="031762001234"} 0"#,
+        // The capture starts in the middle of a group
+        r#"tic2mqtt_malformed_groups_total{meter="031762001234"} 1"#,
+        r#"tic2mqtt_serial_opens_total{meter="031762001234"} 0"#,
+        "tic2mqtt_mqtt_connects_total 1",
+        "# TYPE tic2mqtt_mqtt_publish_seconds summary",
+        r#"tic2mqtt_output_dropped_total{output="MQTT"} 0"#,
+    ] {
+        assert!(body.contains(expected), "{} not in\n{}", expected, body);
+    }
+    // Text labels are not exported
+    assert!(!body.contains(r#"label="PTEC""#), "{}", body);
+    assert_eq!(http_get(&addr, "/other").0, 404);
+
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn metrics_without_mqtt() {
+    let addr = free_addr();
+    let path = std::env::temp_dir().join(format!("tic2mqtt-metrics-{}.lp", std::process::id()));
+    let url = format!("file://{}", path.display());
+    let running = Running::start("historique.tic", &["--mode", "historique", "--outputs", "influx", "--influx-url", &url, "--metrics-listen", &addr]);
+
+    let body = get_until(&addr, "/metrics", |b| b.contains(r#"tic2mqtt_frames_total{meter="031762001234"} 2"#));
+    assert!(body.contains(r#"tic2mqtt_output_dropped_total{output="Influx"} 0"#), "{}", body);
+    // No MQTT output, no MQTT metrics
+    assert!(!body.contains("tic2mqtt_mqtt_"), "{}", body);
+
+    let output = running.finish();
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn rest_api() {
+    let broker = Broker::start();
+    let addr = free_addr();
+    let url = broker.url();
+    let running = Running::start("standard.tic", &["--mode", "standard", "--meter-id", "linky", "--mqtt-server", &url, "--http-listen", &addr]);
+
+    let meters = get_until(&addr, "/meters", |b| b.contains(r#""frames": 1,"#));
+    assert!(meters.starts_with(r#"[{"id": "linky", "mode": "standard", "frames": 1, "timestamp": ""#), "{}", meters);
+
+    let (status, frame) = http_get(&addr, "/meters/linky");
+    assert_eq!(status, 200);
+    assert!(frame.starts_with(r#"{"meter": "linky", "mode": "standard", "timestamp": ""#), "{}", frame);
+    for expected in [r#""date": "2025-01-15T13:00:00+01:00""#, r#""SINSTS": 1500, "#, r#""metrics": {"TOTAL": 19636616"#] {
+        assert!(frame.contains(expected), "{} not in {}", expected, frame);
+    }
+
+    let (status, label) = http_get(&addr, "/meters/linky/labels/SMAXSN");
+    assert_eq!(status, 200);
+    assert!(
+        label.starts_with(r#"{"meter": "linky", "label": "SMAXSN", "value": 1500, "raw": "01500", "date": "2025-01-15T13:00:00+01:00", "unit": "VA", "timestamp": ""#),
+        "{}", label
+    );
+    let (status, label) = http_get(&addr, "/meters/linky/labels/TOTAL");
+    assert_eq!(status, 200);
+    assert!(label.starts_with(r#"{"meter": "linky", "label": "TOTAL", "value": 19636616, "unit": "Wh", "timestamp": ""#), "{}", label);
+
+    assert_eq!(http_get(&addr, "/meters/linky/labels/PAPP"), (404, "{\"error\": \"label PAPP not in the last frame\"}\n".to_string()));
+    assert_eq!(http_get(&addr, "/meters/other").0, 404);
+    assert_eq!(http_get(&addr, "/meters/linky/history?label=SINSTS").0, 404);
+    assert!(http_get(&addr, "/health").1.starts_with(r#"{"status": "#));
+    assert!(http_get(&addr, "/metrics").1.contains(r#"tic2mqtt_frames_total{meter="linky"} 1"#));
+
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn history_of_the_minutes() {
+    let path = std::env::temp_dir().join(format!("tic2mqtt-history-{}.db", std::process::id())).display().to_string();
+    let remove = || for suffix in ["", "-wal", "-shm"] { let _ = std::fs::remove_file(format!("{}{}", path, suffix)); };
+    remove();
+    // Minutes stored by an earlier run, from 2025-01-15T12:00:00Z, kept forever below
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute_batch("CREATE TABLE minutes (
+        meter TEXT NOT NULL, time INTEGER NOT NULL, label TEXT NOT NULL,
+        min REAL NOT NULL, max REAL NOT NULL, avg REAL NOT NULL, last REAL NOT NULL, samples INTEGER NOT NULL,
+        PRIMARY KEY (meter, time, label)
+    ) WITHOUT ROWID;").unwrap();
+    for (minute, label, value) in [(0, "SINSTS", 1500.0), (1, "SINSTS", 1600.0), (2, "SINSTS", 1700.0), (1, "EAST", 19636616.0)] {
+        conn.execute(
+            "INSERT INTO minutes VALUES ('linky', ?1, ?2, ?3, ?4, ?5, ?6, 30)",
+            rusqlite::params![1736942400000i64 + minute * 60000, label, value - 100.0, value + 100.0, value, value + 50.0],
+        ).unwrap();
+    }
+    drop(conn);
+
+    let addr = free_addr();
+    let running = Running::start("standard.tic", &["--mode", "standard", "--meter-id", "linky", "--outputs", "sqlite", "--sqlite-path", &path, "--sqlite-minute-retention", "0", "--http-listen", &addr]);
+    get_until(&addr, "/meters", |b| b.contains(r#""frames": 1,"#));
+
+    let (status, history) = http_get(&addr, "/meters/linky/history?label=SINSTS&from=2025-01-15T12:01:00Z&to=2025-01-15T12:03:00Z");
+    assert_eq!(status, 200);
+    assert_eq!(history, concat!(
+        r#"{"meter": "linky", "label": "SINSTS", "minutes": ["#,
+        r#"{"timestamp": "2025-01-15T12:01:00.000Z", "min": 1500, "max": 1700, "avg": 1600, "last": 1650, "samples": 30}, "#,
+        r#"{"timestamp": "2025-01-15T12:02:00.000Z", "min": 1600, "max": 1800, "avg": 1700, "last": 1750, "samples": 30}]}"#,
+        "\n"
+    ));
+    // Meter times, and the end excluded
+    let (status, history) = http_get(&addr, "/meters/linky/history?label=SINSTS&from=2025-01-15T13:00:00+01:00&to=2025-01-15T13:01:00+01:00");
+    assert_eq!(status, 200);
+    assert!(history.contains(r#"[{"timestamp": "2025-01-15T12:00:00.000Z", "min": 1400, "#) && !history.contains("12:01:00"), "{}", history);
+    // The last day by default
+    assert_eq!(http_get(&addr, "/meters/linky/history?label=SINSTS").1, "{\"meter\": \"linky\", \"label\": \"SINSTS\", \"minutes\": []}\n");
+
+    assert_eq!(http_get(&addr, "/meters/linky/history").0, 400);
+    assert_eq!(
+        http_get(&addr, "/meters/linky/history?label=SINSTS&from=yesterday"),
+        (400, "{\"error\": \"invalid from yesterday, expected e.g. 2025-01-15T12:00:00Z\"}\n".to_string())
+    );
+    assert_eq!(http_get(&addr, "/meters/other/history?label=SINSTS").0, 404);
+
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    remove();
+}
+
+#[test]
+fn live_stream() {
+    let broker = Broker::start();
+    let addr = free_addr();
+    let url = broker.url();
+    let mut running = Running::spawn(&["--mode", "historique", "--mqtt-server", &url, "--http-listen", &addr]);
+
+    // Wait for the server
+    http_get(&addr, "/meters");
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    write!(stream, "GET /stream?meter=031762001234&labels=PAPP,TOTAL HTTP/1.1\r\nHost: {}\r\n\r\n", addr).unwrap();
+    let mut lines = BufReader::new(stream).lines().map(|line| line.unwrap());
+    let head: Vec<String> = lines.by_ref().take_while(|line| !line.is_empty()).collect();
+    assert_eq!(head[0], "HTTP/1.1 200 OK");
+    assert!(head.contains(&"Content-Type: text/event-stream".to_string()), "{:?}", head);
+    // Subscribed once the first event is received
+    assert_eq!(lines.next().unwrap(), "retry: 5000");
+
+    // The last frame of the fixture ends with the input
+    running.feed("historique.tic");
+    let events: Vec<String> = lines.filter(|line| line.starts_with("data: ")).take(2).collect();
+    for (event, papp) in events.iter().zip([1500, 1351]) {
+        assert!(event.starts_with(r#"data: {"meter": "031762001234", "mode": "historique", "timestamp": ""#), "{}", event);
+        let expected = format!(r#", "labels": {{"PAPP": {}}}, "metrics": {{"TOTAL": "#, papp);
+        assert!(event.contains(&expected), "{} not in {}", expected, event);
+    }
+
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// Open `GET /stream`, returning the connection once the headers are read, and the status
+fn open_stream(addr: &str) -> (BufReader<TcpStream>, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    write!(stream, "GET /stream HTTP/1.1\r\nHost: {}\r\n\r\n", addr).unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status).unwrap();
+    (reader, status.trim_end().to_string())
+}
+
+#[test]
+fn connections_are_limited() {
+    let addr = free_addr();
+    let running = Running::spawn(&["--mode", "historique", "--outputs", "json", "--json-output", "-", "--http-listen", &addr]);
+    http_get(&addr, "/meters");
+
+    // HEAD answers the headers alone, without subscribing
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    write!(stream, "HEAD /stream HTTP/1.1\r\nHost: {}\r\n\r\n", addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n") && response.ends_with("\r\n\r\n"), "{}", response);
+
+    // 16 clients of the stream
+    let streams: Vec<_> = (0..16).map(|_| open_stream(&addr)).collect();
+    assert!(streams.iter().all(|(_, status)| status == "HTTP/1.1 200 OK"));
+    assert_eq!(http_get(&addr, "/stream"), (503, "{\"error\": \"too many stream clients\"}\n".to_string()));
+    assert_eq!(http_get(&addr, "/meters").0, 200);
+
+    // 32 connections, counting the streams
+    let idle: Vec<_> = (0..16).map(|_| TcpStream::connect(&addr).unwrap()).collect();
+    assert_eq!(http_get(&addr, "/meters"), (503, "too many connections\n".to_string()));
+    // A refused client sending its request a byte at a time is cut off after a second
+    let mut slow = TcpStream::connect(&addr).unwrap();
+    slow.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let start = Instant::now();
+    let mut reader = slow.try_clone().unwrap();
+    std::thread::spawn(move || {
+        for _ in 0..20 {
+            if slow.write_all(b"G").is_err() { break; }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    });
+    let mut response = Vec::new();
+    let _ = reader.read_to_end(&mut response);
+    assert!(start.elapsed() < Duration::from_secs(3), "{:?}", start.elapsed());
+
+    drop(idle);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while http_get(&addr, "/meters").0 != 200 {
+        assert!(Instant::now() < deadline, "connections not freed");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    drop(streams);
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// GET /health until its status is `status`
+fn health_until(addr: &str, status: u16) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let (last, body) = http_get(addr, "/health");
+        if last == status { return body; }
+        assert!(Instant::now() < deadline, "timed out, last response {}:\n{}", last, body);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn health_check() {
+    let broker = Broker::start();
+    let addr = free_addr();
+    let url = broker.url();
+    let running = Running::start("standard.tic", &["--mode", "standard", "--meter-id", "linky", "--mqtt-server", &url, "--http-listen", &addr]);
+
+    let body = health_until(&addr, 200);
+    for expected in [
+        r#"{"status": "ok", "meters": [{"id": "linky", "input": "open", "last_frame_age": "#,
+        r#""mqtt": {"connected": true}"#,
+        r#""outputs": [{"name": "MQTT", "queued": "#,
+        r#""dropped": 0, "healthy": true}, {"name": "Stream", "#,
+    ] {
+        assert!(body.contains(expected), "{} not in {}", expected, body);
+    }
+    let output = tic2mqtt(&["--healthcheck", "--http-listen", &addr]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with(r#"{"status": "ok""#));
+    // The address alone is read from the configuration, the other settings are not checked
+    let config = std::env::temp_dir().join(format!("tic2mqtt-test-{}-healthcheck.toml", std::process::id())).display().to_string();
+    std::fs::write(&config, format!("[serial]\nmode = \"other\"\n\n[http]\nlisten = \"{}\"\n", addr)).unwrap();
+    let output = tic2mqtt(&["--healthcheck", "--config", &config]);
+    std::fs::remove_file(&config).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Nothing listening any more
+    assert_eq!(tic2mqtt(&["--healthcheck", "--http-listen", &addr]).status.code(), Some(1));
+    // Every failure is 1, 2 being reserved by Docker
+    for args in [&["--healthcheck"][..], &["--healthcheck", "--http-listen", "8080"], &["--healthcheck", "--config", "/nonexistent.toml"], &["--healthcheck", "--other"], &["--other", "--healthcheck"], &["--healthcheck=yes"]] {
+        assert_eq!(tic2mqtt(args).status.code(), Some(1), "{:?}", args);
+    }
+}
+
+#[test]
+fn unhealthy_without_broker() {
+    let addr = free_addr();
+    let server = format!("tcp://{}", free_addr());
+    let running = Running::start("standard.tic", &["--mode", "standard", "--mqtt-server", &server, "--http-listen", &addr]);
+
+    let body = health_until(&addr, 503);
+    assert!(body.starts_with(r#"{"status": "unhealthy", "#), "{}", body);
+    assert!(body.contains(r#""mqtt": {"connected": false}"#), "{}", body);
+    let output = tic2mqtt(&["--healthcheck", "--http-listen", &addr]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with(r#"{"status": "unhealthy""#));
+
+    running.finish();
+}
@@ -0,0 +1,109 @@
+//! End-to-end tests of the InfluxDB output: frames replayed through tic2mqtt as line protocol.
+
+mod common;
+
+use std::net::{TcpListener, UdpSocket};
+use std::time::{Duration, Instant};
+
+use common::{Broker, fixture, replay, tic2mqtt};
+
+/// DATE of the standard fixture, H250115130000: 2025-01-15 13:00 in winter time
+const STANDARD_DATE_NS: &str = "1736942400000000000";
+
+#[test]
+fn points_appended_to_a_file() {
+    let broker = Broker::start();
+    let path = std::env::temp_dir().join(format!("tic2mqtt-influx-{}.lp", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let url = format!("file://{}", path.display());
+    replay(&broker, "standard.tic", "standard", &["--meter-id", "linky", "--influx-url", &url]);
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", content);
+    for line in &lines {
+        assert!(line.starts_with(r"linky,mode=standard,tariff=TEMPO,period=HP\ BLEU "), "{}", line);
+        assert!(line.ends_with(&format!(" {}", STANDARD_DATE_NS)), "{}", line);
+        for field in [r#"ADSC="021728123456""#, "EAST=19636616i", r#"LTARF="HP BLEU""#, "NJOURF+1=0i", "TOTAL=19636616i"] {
+            assert!(line.contains(field), "{} not in {}", field, line);
+        }
+    }
+    assert!(lines[0].contains("SINSTS=1500i,"), "{}", lines[0]);
+    assert!(lines[1].contains("SINSTS=1528i,"), "{}", lines[1]);
+    // MQTT still gets the frames
+    assert_eq!(broker.payloads("tic2mqtt/linky").len(), 2);
+}
+
+#[test]
+fn points_sent_over_udp() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let url = format!("udp://{}", socket.local_addr().unwrap());
+    let broker = Broker::start();
+    replay(&broker, "historique.tic", "historique", &["--influx-url", &url]);
+
+    let mut buf = [0u8; 4096];
+    let mut lines = Vec::new();
+    while let Ok(n) = socket.recv(&mut buf) {
+        lines.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+        if lines.len() == 3 { break; }
+    }
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    // No DATE in historique mode: the time of reception
+    let line = &lines[1];
+    assert!(line.starts_with("031762001234,mode=historique,tariff=HC..,period=HC.. "), "{}", line);
+    for field in ["HCHC=5101233i", "PAPP=1351i", "TOTAL=10742683i"] {
+        assert!(line.contains(field), "{} not in {}", field, line);
+    }
+    let nanos: u128 = line.rsplit(' ').next().unwrap().parse().unwrap();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+    assert!(now - nanos < 60_000_000_000, "{}", line);
+}
+
+#[test]
+fn influx_output_alone() {
+    let path = std::env::temp_dir().join(format!("tic2mqtt-influx-alone-{}.lp", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let url = format!("file://{}", path.display());
+    // No broker is listening there
+    let output = tic2mqtt(&["--replay", &fixture("historique.tic"), "--mode", "historique", "--outputs", "influx", "--influx-url", &url, "--mqtt-server", "tcp://127.0.0.1:9"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(content.lines().count(), 3, "{}", content);
+}
+
+#[test]
+fn unresponsive_http_endpoint_times_out() {
+    // Accepts the connections, and never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/write?db=tic", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let accepted: Vec<_> = listener.incoming().flatten().collect();
+        drop(accepted);
+    });
+    let start = Instant::now();
+    let output = tic2mqtt(&["--replay", &fixture("standard.tic"), "--mode", "standard", "--outputs", "influx", "--influx-url", &url]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Two frames, each given up after the timeout
+    assert!(start.elapsed() < Duration::from_secs(20), "{:?}", start.elapsed());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("[Influx]").count(), 2, "{}", stderr);
+}
+
+#[test]
+fn invalid_influx_output_is_rejected() {
+    let output = tic2mqtt(&["--serial", "-", "--influx-url", "tcp://localhost:8086"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--influx-url"));
+
+    let output = tic2mqtt(&["--serial", "-", "--outputs", "mqtt,influx"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires --influx-url"));
+
+    let output = tic2mqtt(&["--serial", "-", "--outputs", "mqtt,kafka"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--outputs"));
+}
@@ -0,0 +1,85 @@
+//! End-to-end tests of the JSON lines output, and of running without a reachable MQTT broker.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{Broker, Packet, Running, fixture, free_addr, tic2mqtt};
+
+#[test]
+fn json_lines_to_stdout_without_mqtt() {
+    let output = tic2mqtt(&["--replay", &fixture("standard.tic"), "--mode", "standard", "--meter-id", "linky", "--outputs", "json", "--json-output", "-"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    for line in &lines {
+        assert!(line.starts_with(r#"{"meter": "linky", "mode": "standard", "timestamp": ""#), "{}", line);
+        assert!(line.ends_with("}}"), "{}", line);
+        for expected in [
+            r#""date": "2025-01-15T13:00:00+01:00""#,
+            r#""labels": {"ADSC": "021728123456", "#,
+            r#""EAST": 19636616, "#,
+            r#""LTARF": "    HP BLEU     ", "#,
+            r#""metrics": {"TOTAL": 19636616, "#,
+        ] {
+            assert!(line.contains(expected), "{} not in {}", expected, line);
+        }
+    }
+    assert!(lines[1].contains(r#""SINSTS": 1528, "#), "{}", lines[1]);
+    // Nothing tried with MQTT
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("[MQTT]"));
+}
+
+#[test]
+fn json_lines_to_a_file() {
+    let path = std::env::temp_dir().join(format!("tic2mqtt-json-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path_arg = path.display().to_string();
+    // The broker is down: the frames are still written, and the bridge exits cleanly
+    let unreachable = format!("tcp://{}", free_addr());
+    let output = tic2mqtt(&["--replay", &fixture("historique.tic"), "--mode", "historique", "--json-output", &path_arg, "--mqtt-server", &unreachable]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[MQTT] cannot connect"));
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(content.lines().count(), 3, "{}", content);
+    assert!(content.lines().all(|l| l.starts_with(r#"{"meter": "031762001234", "mode": "historique", "#) && !l.contains(r#""date""#)), "{}", content);
+    assert!(content.contains(r#""PTEC": "HC.."}"#), "{}", content);
+}
+
+#[test]
+fn mqtt_reconnects_without_frames() {
+    let broker = Broker::start();
+    let url = broker.url();
+    let running = Running::start("historique.tic", &["--mode", "historique", "--mqtt-server", &url]);
+    let connects = |n: usize| move |p: &[Packet]| p.iter().filter(|p| matches!(p, Packet::Connect { .. })).count() == n;
+    assert!(broker.wait_for(Duration::from_secs(5), connects(1)), "no connection");
+    // The last frame of the fixture ends with the input
+    assert!(broker.wait_for(Duration::from_secs(5), |_| broker.payloads("tic2mqtt/031762001234").len() == 2), "no frames");
+
+    // The meter is silent from now on, the connection is restored all the same
+    broker.drop_clients();
+    assert!(broker.wait_for(Duration::from_secs(10), connects(2)), "no reconnection: {:?}", broker.packets());
+
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[MQTT] connection lost, reconnecting"));
+}
+
+#[test]
+fn mqtt_connects_once_the_broker_is_up() {
+    let addr = free_addr();
+    let url = format!("tcp://{}", addr);
+    let running = Running::start("historique.tic", &["--mode", "historique", "--mqtt-server", &url]);
+    std::thread::sleep(Duration::from_millis(500));
+    let broker = Broker::start_on(&addr);
+    assert!(broker.wait_for(Duration::from_secs(10), |p| p.iter().any(|p| matches!(p, Packet::Connect { .. }))), "no connection");
+
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(broker.wait_for(Duration::from_secs(5), |p| p.contains(&Packet::Disconnect)), "no disconnect");
+    assert_eq!(broker.payloads("tic2mqtt/status"), ["online", "offline"]);
+}
@@ -0,0 +1,425 @@
+//! End-to-end tests: recorded frames replayed through tic2mqtt to an in-process MQTT broker.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{Broker, Message, Packet, Running, fixture, replay, tic2mqtt};
+use tic2mqtt::tic::{TicModeEnum, TicModeHandle};
+
+const METER: &str = "031762001234";
+
+fn retained(topic: &str, payload: &str) -> Message {
+    Message { topic: topic.to_string(), payload: payload.to_string(), qos: 1, retain: true }
+}
+
+fn config_topic(meter: &str, label: &str) -> String {
+    format!("homeassistant/sensor/{}/tic2mqtt_{}_{}/config", meter, meter, label)
+}
+
+/// Discovery config topics published, sorted, without the configs cleared
+fn config_topics(broker: &Broker) -> Vec<String> {
+    let mut topics: Vec<String> = broker.publishes().into_iter()
+        .filter(|m| m.topic.ends_with("/config") && !m.payload.is_empty())
+        .map(|m| m.topic)
+        .collect();
+    topics.sort();
+    topics
+}
+
+fn write_config(name: &str, content: &str) -> String {
+    let path = std::env::temp_dir().join(format!("tic2mqtt-test-{}-{}.toml", std::process::id(), name));
+    std::fs::write(&path, content).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn historique_frames_are_published() {
+    let broker = Broker::start();
+    let output = replay(&broker, "historique.tic", "historique", &["--discovery", "seen"]);
+
+    let states = broker.published_to(&format!("tic2mqtt/{}", METER));
+    assert_eq!(states.len(), 3);
+    // Nothing consumed since the start of the periods
+    let counters = ["TOTAL", "HCHC", "HCHP"].iter()
+        .flat_map(|index| ["today", "this_week", "this_month"].map(|period| format!(r#""{}_{}": {{"value": 0}}"#, index, period)))
+        .collect::<Vec<_>>()
+        .join(",");
+    assert!(states.iter().all(|m| m.qos == 1 && !m.retain));
+    assert_eq!(
+        states[0].payload,
+        format!("{}{}}}", r#"{"ADCO": {"raw": "031762001234"},"HCHC": {"raw": "005101233"},"HCHP": {"raw": "005641450"},"HHPHC": {"raw": "A"},"IINST": {"raw": "007"},"IMAX": {"raw": "090"},"ISOUSC": {"raw": "30"},"MOTDETAT": {"raw": "000000"},"OPTARIF": {"raw": "HC.."},"PAPP": {"raw": "01500"},"PTEC": {"raw": "HP.."},"TOTAL": {"value": 10742683},"#, counters)
+    );
+    // Off-peak hours from 22:00, and the group with a bad checksum keeps its previous value
+    assert_eq!(
+        states[2].payload,
+        format!("{}{}}}", r#"{"ADCO": {"raw": "031762001234"},"HCHC": {"raw": "005101233"},"HCHP": {"raw": "005641450"},"HHPHC": {"raw": "A"},"IINST": {"raw": "007"},"IMAX": {"raw": "090"},"ISOUSC": {"raw": "30"},"MOTDETAT": {"raw": "000000"},"OPTARIF": {"raw": "HC.."},"PAPP": {"raw": "01511"},"PTEC": {"raw": "HC.."},"TOTAL": {"value": 10742683},"#, counters)
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(r#"checksum error on "MOTDETAT 000100 B": expected 'C', found 'B'"#), "{}", stderr);
+}
+
+#[test]
+fn discovery_configs_of_seen_labels() {
+    let broker = Broker::start();
+    replay(&broker, "historique.tic", "historique", &["--discovery", "seen"]);
+
+    let labels = ["ADCO", "HCHC", "HCHP", "HHPHC", "IINST", "IMAX", "ISOUSC", "MOTDETAT", "OPTARIF", "PAPP", "PTEC", "TOTAL"];
+    let counters = ["HCHC", "HCHP", "TOTAL"].iter().flat_map(|index| ["today", "this_week", "this_month"].map(|period| format!("{}_{}", index, period)));
+    let event_topic = format!("homeassistant/event/{}/tic2mqtt_{}_alert/config", METER, METER);
+    let mut expected: Vec<String> = std::iter::once(event_topic.clone())
+        .chain(labels.into_iter().map(String::from).chain(counters).map(|l| config_topic(METER, &l)))
+        .collect();
+    expected.sort();
+    assert_eq!(config_topics(&broker), expected);
+    // Each config is published once, retained
+    for topic in &expected {
+        let messages = broker.published_to(topic);
+        assert_eq!(messages.len(), 1, "{}", topic);
+        assert!(messages[0].retain && messages[0].qos == 1, "{}", topic);
+    }
+
+    let availability = r#""availability":[{"topic":"tic2mqtt/status"},{"topic":"tic2mqtt/031762001234/status"}],"availability_mode":"all""#;
+    let device = r#""device":{"identifiers":["tic2mqtt_031762001234"],"manufacturer":"Enedis","model":"TIC historique","name":"Télé-information client 031762001234"}"#;
+    assert_eq!(
+        broker.payloads(&config_topic(METER, "PAPP")),
+        [format!(
+            r#"{{"name":"TIC PAPP","state_topic":"tic2mqtt/031762001234","unique_id":"tic2mqtt_031762001234_PAPP","value_template":"{{{{ value_json['PAPP'] | default({{}}) | attr('raw') | default('') }}}}",{},{},"device_class":"apparent_power","state_class":"measurement","unit_of_measurement":"VA"}}"#,
+            availability, device
+        )]
+    );
+    assert_eq!(
+        broker.payloads(&config_topic(METER, "HCHC")),
+        [format!(
+            r#"{{"name":"TIC HCHC","state_topic":"tic2mqtt/031762001234","unique_id":"tic2mqtt_031762001234_HCHC","value_template":"{{{{ value_json['HCHC'] | default({{}}) | attr('raw') | default('') }}}}",{},{},"device_class":"energy","state_class":"total_increasing","unit_of_measurement":"Wh"}}"#,
+            availability, device
+        )]
+    );
+    // The synthetic total index, for the energy dashboard
+    assert_eq!(
+        broker.payloads(&config_topic(METER, "TOTAL")),
+        [format!(
+            r#"{{"name":"TIC total energy","state_topic":"tic2mqtt/031762001234","unique_id":"tic2mqtt_031762001234_TOTAL","value_template":"{{{{ value_json['TOTAL'] | default({{}}) | attr('value') | default('') }}}}",{},{},"device_class":"energy","state_class":"total_increasing","unit_of_measurement":"Wh"}}"#,
+            availability, device
+        )]
+    );
+    assert_eq!(
+        broker.payloads(&event_topic),
+        [format!(
+            r#"{{"name":"TIC alert","state_topic":"tic2mqtt/031762001234/event","unique_id":"tic2mqtt_031762001234_alert","event_types":["alert_start","alert_end"],{},{}}}"#,
+            availability, device
+        )]
+    );
+    assert_eq!(
+        broker.payloads(&config_topic(METER, "PTEC")),
+        [format!(
+            r#"{{"name":"TIC PTEC","state_topic":"tic2mqtt/031762001234","unique_id":"tic2mqtt_031762001234_PTEC","value_template":"{{{{ value_json['PTEC'] | default({{}}) | attr('raw') | default('') }}}}",{},{}}}"#,
+            availability, device
+        )]
+    );
+}
+
+#[test]
+fn three_phase_historique() {
+    let broker = Broker::start();
+    replay(&broker, "triphase.tic", "historique", &["--discovery", "seen"]);
+
+    let meter = "031762005678";
+    let states = broker.payloads(&format!("tic2mqtt/{}", meter));
+    assert_eq!(states.len(), 3);
+    assert!(states[0].contains(r#""PPOT": {"raw": "00", "phase1": true, "phase2": true, "phase3": true}"#), "{}", states[0]);
+    // The overcurrent warning of the short frame holds for that frame only
+    assert!(!states[0].contains("ADIR2"), "{}", states[0]);
+    assert!(states[1].contains(r#""ADIR2": {"raw": "065"}"#), "{}", states[1]);
+    assert!(!states[2].contains("ADIR2"), "{}", states[2]);
+    // Phase 2 potential missing
+    assert!(states[2].contains(r#""PPOT": {"raw": "04", "phase1": true, "phase2": false, "phase3": true}"#), "{}", states[2]);
+
+    // Alert events, in order, timestamped
+    let events = broker.published_to(&format!("tic2mqtt/{}/event", meter));
+    assert!(events.iter().all(|m| m.qos == 1 && !m.retain));
+    let (events, timestamps): (Vec<&str>, Vec<&str>) = events.iter().map(|m| m.payload.split_once(r#", "timestamp": "#).unwrap()).unzip();
+    assert_eq!(
+        events,
+        [
+            r#"{"event_type": "alert_start", "alert": "overcurrent_phase2", "label": "ADIR2", "value": "065""#,
+            r#"{"event_type": "alert_start", "alert": "missing_phase2", "label": "PPOT", "value": "04""#,
+            r#"{"event_type": "alert_end", "alert": "overcurrent_phase2", "label": "ADIR2", "value": "065""#,
+        ]
+    );
+    assert!(timestamps.iter().all(|t| t.len() == 27 && t.ends_with("Z\"}")), "{:?}", timestamps);
+
+    let binary_topic = |object: &str| format!("homeassistant/binary_sensor/{}/tic2mqtt_{}_{}/config", meter, meter, object);
+    let topics = config_topics(&broker);
+    assert!(!topics.contains(&config_topic(meter, "PPOT")) && !topics.contains(&config_topic(meter, "ADIR2")), "{:?}", topics);
+    let ppot = broker.payloads(&binary_topic("PPOT_2"));
+    assert!(ppot[0].starts_with(r#"{"name":"TIC phase 2 PPOT","#), "{}", ppot[0]);
+    assert!(ppot[0].contains(r#""value_template":"{{ 'ON' if value_json['PPOT'] | default({}) | attr('phase2') else 'OFF' }}""#), "{}", ppot[0]);
+    assert!(ppot[0].ends_with(r#""device_class":"power"}"#), "{}", ppot[0]);
+    let adir = broker.payloads(&binary_topic("ADIR2"));
+    assert!(adir[0].contains(r#""value_template":"{{ 'ON' if 'ADIR2' in value_json else 'OFF' }}""#), "{}", adir[0]);
+    assert!(adir[0].ends_with(r#""device_class":"problem"}"#), "{}", adir[0]);
+
+    let iinst = broker.payloads(&config_topic(meter, "IINST1"));
+    assert!(iinst[0].starts_with(r#"{"name":"TIC phase 1 IINST1","#), "{}", iinst[0]);
+    assert!(iinst[0].ends_with(r#""device_class":"current","state_class":"measurement","unit_of_measurement":"A"}"#), "{}", iinst[0]);
+    // The maximum currents are not measurements
+    let imax = broker.payloads(&config_topic(meter, "IMAX3"));
+    assert!(imax[0].ends_with(r#""device_class":"current","unit_of_measurement":"A","entity_category":"diagnostic"}"#), "{}", imax[0]);
+}
+
+#[test]
+fn availability_follows_bridge_and_meter() {
+    let broker = Broker::start();
+    replay(&broker, "historique.tic", "historique", &[]);
+
+    let packets = broker.packets();
+    let Some(Packet::Connect { client_id, will, .. }) = packets.first() else { panic!("no connect: {:?}", packets) };
+    assert_eq!(client_id, "tic2mqtt_client");
+    assert_eq!(will.as_ref(), Some(&retained("tic2mqtt/status", "offline")));
+
+    let meter_status = format!("tic2mqtt/{}/status", METER);
+    let availability: Vec<Message> = broker.publishes().into_iter().filter(|m| m.topic.ends_with("/status")).collect();
+    assert_eq!(
+        availability,
+        [
+            retained("tic2mqtt/status", "online"),
+            retained(&meter_status, "online"),
+            retained(&meter_status, "offline"),
+            retained("tic2mqtt/status", "offline"),
+        ]
+    );
+    // The meter is online before its first state
+    let topics: Vec<String> = broker.publishes().into_iter().map(|m| m.topic).collect();
+    let online = topics.iter().position(|t| *t == meter_status).unwrap();
+    let first_state = topics.iter().position(|t| *t == format!("tic2mqtt/{}", METER)).unwrap();
+    assert!(online < first_state);
+    assert_eq!(packets.last(), Some(&Packet::Disconnect));
+}
+
+#[test]
+fn standard_meter_with_name_and_prefix() {
+    let broker = Broker::start();
+    replay(&broker, "standard.tic", "standard", &["--meter-id", "linky", "--mqtt-topic-prefix", "home/tic", "--discovery", "all"]);
+
+    let states = broker.payloads("home/tic/linky");
+    assert_eq!(states.len(), 2);
+    for expected in [
+        r#""ADSC": {"raw": "021728123456"}"#,
+        r#""LTARF": {"raw": "    HP BLEU     "}"#,
+        r#""NGTF": {"raw": "     TEMPO      "}"#,
+        r#""SINSTS2": {"raw": "00500"}"#,
+        r#""SMAXSN": {"raw": "01500"}"#,
+        r#""STGE": {"raw": "05000000", "overvoltage": false, "overload": false, "producer": false, "injecting": false}"#,
+        r#""DATE": {"raw": ""}"#,
+    ] {
+        assert!(states[0].contains(expected), "{} not in {}", expected, states[0]);
+    }
+
+    // Every standard label is announced under the configured name
+    let mut expected: Vec<String> = TicModeHandle::new(TicModeEnum::Standard).labels().iter().map(|l| config_topic("linky", &l.replace('+', "_"))).collect();
+    expected.push("homeassistant/event/linky/tic2mqtt_linky_alert/config".to_string());
+    expected.push(config_topic("linky", "active_power"));
+    expected.push(config_topic("linky", "TOTAL"));
+    // The counters of the consumption index and of the total, once computed
+    for index in ["EAST", "TOTAL"] {
+        for period in ["today", "this_week", "this_month"] {
+            expected.push(config_topic("linky", &format!("{}_{}", index, period)));
+        }
+    }
+    for flag in ["overload", "overvoltage", "producer", "injecting"] {
+        expected.push(format!("homeassistant/binary_sensor/linky/tic2mqtt_linky_STGE_{}/config", flag));
+    }
+    expected.sort();
+    assert_eq!(config_topics(&broker), expected);
+    let east = broker.payloads(&config_topic("linky", "EAST"));
+    assert!(east[0].contains(r#""state_topic":"home/tic/linky""#), "{}", east[0]);
+    assert!(east[0].contains(r#""availability":[{"topic":"home/tic/status"},{"topic":"home/tic/linky/status"}]"#), "{}", east[0]);
+    assert!(east[0].contains(r#""model":"TIC standard""#), "{}", east[0]);
+    // The derived active power, not in the states until its window is filled
+    let power = broker.payloads(&config_topic("linky", "active_power"));
+    assert!(power[0].starts_with(r#"{"name":"TIC active power","state_topic":"home/tic/linky","unique_id":"tic2mqtt_linky_active_power","value_template":"{{ value_json['active_power'] | default({}) | attr('value') | default('') }}""#), "{}", power[0]);
+    assert!(power[0].ends_with(r#""device_class":"power","state_class":"measurement","unit_of_measurement":"W"}"#), "{}", power[0]);
+    assert!(!states[1].contains("active_power"), "{}", states[1]);
+    assert_eq!(broker.payloads("home/tic/linky/status"), ["online", "offline"]);
+}
+
+#[test]
+fn no_discovery() {
+    let broker = Broker::start();
+    replay(&broker, "historique.tic", "historique", &["--discovery", "none"]);
+    assert_eq!(config_topics(&broker), Vec::<String>::new());
+    assert_eq!(broker.payloads(&format!("tic2mqtt/{}", METER)).len(), 3);
+}
+
+#[test]
+fn label_overrides_from_config_file() {
+    let broker = Broker::start();
+    let config = write_config("labels", "[labels.PAPP]\nname = \"Puissance\"\nunit = \"\"\n\n[labels.ADCO]\nenabled = false\n");
+    replay(&broker, "historique.tic", "historique", &["--config", &config, "--discovery", "seen"]);
+    std::fs::remove_file(config).unwrap();
+
+    let topics = config_topics(&broker);
+    assert!(!topics.contains(&config_topic(METER, "ADCO")));
+    let papp = broker.payloads(&config_topic(METER, "PAPP"));
+    assert!(papp[0].starts_with(r#"{"name":"Puissance","#), "{}", papp[0]);
+    assert!(!papp[0].contains("unit_of_measurement"), "{}", papp[0]);
+}
+
+#[test]
+fn several_meters_share_the_connection() {
+    let broker = Broker::start();
+    let config = write_config(
+        "meters",
+        &format!(
+            "[[meters]]\nid = \"consumption\"\nreplay = {:?}\nmode = \"historique\"\n\n[[meters]]\nid = \"production\"\nreplay = {:?}\nmode = \"standard\"\n",
+            fixture("historique.tic"),
+            fixture("standard.tic")
+        ),
+    );
+    let url = broker.url();
+    let output = tic2mqtt(&["--config", &config, "--mqtt-server", &url, "--discovery", "none"]);
+    std::fs::remove_file(config).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(broker.wait_for(std::time::Duration::from_secs(5), |p| p.contains(&Packet::Disconnect)));
+
+    let connects = broker.packets().into_iter().filter(|p| matches!(p, Packet::Connect { .. })).count();
+    assert_eq!(connects, 1);
+    assert_eq!(broker.payloads("tic2mqtt/consumption").len(), 3);
+    assert_eq!(broker.payloads("tic2mqtt/production").len(), 2);
+    assert_eq!(broker.payloads("tic2mqtt/consumption/status"), ["online", "offline"]);
+    assert_eq!(broker.payloads("tic2mqtt/production/status"), ["online", "offline"]);
+}
+
+#[test]
+fn tariff_of_each_meter() {
+    let broker = Broker::start();
+    let meters = format!(
+        "[[meters]]\nid = \"consumption\"\nreplay = {:?}\nmode = \"historique\"\n\n[meters.tariff.prices]\nHCHC = 0.1\nHCHP = 0.2\n\n\
+         [[meters]]\nid = \"production\"\nreplay = {:?}\nmode = \"standard\"\n",
+        fixture("historique.tic"),
+        fixture("standard.tic")
+    );
+    let config = write_config("meter-tariffs", &format!("{}\n[tariff.prices]\nEASF01 = 0.1696\nEASF02 = 0.2146\nHCHC = 0.3\n", meters));
+    let url = broker.url();
+    let output = tic2mqtt(&["--config", &config, "--mqtt-server", &url, "--discovery", "none"]);
+    std::fs::remove_file(&config).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Peak hours, then the period of the standard meter
+    assert!(broker.payloads("tic2mqtt/consumption")[0].contains(r#""rate": {"value": 0.2}"#));
+    assert!(broker.payloads("tic2mqtt/production")[0].contains(r#""rate": {"value": 0.2146}"#));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("tariff.prices in"), "{}", stderr);
+    assert!(stderr.contains(": HCHC never sent by meter production in standard mode"), "{}", stderr);
+
+    let config = write_config("bad-meter-tariff", &meters.replace("HCHP = 0.2", "EASF01 = 0.2"));
+    let output = tic2mqtt(&["--config", &config]);
+    std::fs::remove_file(&config).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("meters[0].tariff.prices.EASF01 in") && stderr.contains("unknown label for historique mode"), "{}", stderr);
+}
+
+#[test]
+fn counters_are_kept_in_the_state_file() {
+    let broker = Broker::start();
+    // Saved on the day before the frames of the fixture
+    let state = write_config(
+        "state",
+        "[linky.EAST.day]\nsince = \"2025-01-14\"\nstart = 19636000\n\n[linky.EAST.month]\nsince = \"2025-01-01\"\nstart = 19600000\n",
+    );
+    replay(&broker, "standard.tic", "standard", &["--meter-id", "linky", "--discovery", "none", "--state-file", &state]);
+    let saved = std::fs::read_to_string(&state).unwrap();
+    std::fs::remove_file(&state).unwrap();
+
+    let states = broker.payloads("tic2mqtt/linky");
+    for expected in [r#""EAST_today": {"value": 0}"#, r#""EAST_yesterday": {"value": 616}"#, r#""EAST_this_month": {"value": 36616}"#] {
+        assert!(states[0].contains(expected), "{} not in {}", expected, states[0]);
+    }
+    assert!(saved.contains("[linky.EAST.day]\nsince = \"2025-01-15\"\nstart = 19636616\nprevious = 616\n"), "{}", saved);
+}
+
+#[test]
+fn costs_from_the_tariff() {
+    let broker = Broker::start();
+    let config = write_config("tariff", "[tariff]\nsubscription = 15.5\n\n[tariff.prices]\nEASF01 = 0.1696\nEASF02 = 0.2146\n");
+    replay(&broker, "standard.tic", "standard", &["--config", &config, "--meter-id", "linky", "--discovery", "seen"]);
+    std::fs::remove_file(config).unwrap();
+
+    // Rate of the tariff period in progress (NTARF 02), and the subscription spread over January, 15 days so far
+    let states = broker.payloads("tic2mqtt/linky");
+    for expected in [r#""rate": {"value": 0.2146}"#, r#""cost_today": {"value": 0.5}"#, r#""cost_this_month": {"value": 7.5}"#] {
+        assert!(states[0].contains(expected), "{} not in {}", expected, states[0]);
+    }
+    let cost = broker.payloads(&config_topic("linky", "cost_today"));
+    assert!(cost[0].contains(r#""device_class":"monetary","state_class":"total","unit_of_measurement":"EUR""#), "{}", cost[0]);
+    let rate = broker.payloads(&config_topic("linky", "rate"));
+    assert!(rate[0].contains(r#""unit_of_measurement":"EUR/kWh""#) && !rate[0].contains("device_class"), "{}", rate[0]);
+
+    let config = write_config("bad-tariff", "[tariff.prices]\nHCHC = 0.2\n");
+    let output = tic2mqtt(&["--config", &config, "--replay", &fixture("standard.tic"), "--mode", "standard"]);
+    std::fs::remove_file(&config).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tariff.prices.HCHC in"), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn self_consumption_from_the_production_topic() {
+    let broker = Broker::start();
+    broker.retain("solar/energy", "5000");
+    let url = broker.url();
+    let mut running = Running::spawn(&["--mode", "standard", "--mqtt-server", &url, "--meter-id", "linky", "--discovery", "none", "--production-topic", "solar/energy"]);
+    let subscribed = broker.wait_for(Duration::from_secs(5), |p| p.contains(&Packet::Subscribe(vec!["solar/energy".to_string()])));
+    assert!(subscribed, "{:?}", broker.packets());
+    // The retained index follows the acknowledgement of the subscription
+    std::thread::sleep(Duration::from_millis(200));
+    running.feed("producer.tic");
+    let output = running.finish();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let states = broker.payloads("tic2mqtt/linky");
+    for expected in [r#""PRODUCTION_today": {"value": 0}"#, r#""SELF_today": {"value": 0}"#] {
+        assert!(states[0].contains(expected), "{} not in {}", expected, states[0]);
+    }
+
+    let output = tic2mqtt(&["--replay", &fixture("producer.tic"), "--mode", "standard", "--outputs", "json", "--json-output", "-", "--production-topic", "solar/energy"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--production-topic requires the mqtt output"));
+}
+
+#[test]
+fn legacy_discovery_configs_are_cleared() {
+    let broker = Broker::start();
+    replay(&broker, "historique.tic", "historique", &["--discovery", "seen"]);
+
+    // Once, even for the labels not seen
+    for label in ["PAPP", "ADPS"] {
+        let messages = broker.published_to(&format!("homeassistant/sensor/{}/tic2mqtt_{}/config", METER, label));
+        assert_eq!(messages.len(), 1, "{}", label);
+        assert!(messages[0].payload.is_empty() && messages[0].retain, "{}", label);
+    }
+}
+
+#[test]
+fn invalid_configuration_is_rejected() {
+    let output = tic2mqtt(&["--replay", &fixture("historique.tic"), "--mqtt-qos", "3"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "error: invalid value '3' for --mqtt-qos: expected 0, 1 or 2\n");
+}
+
+#[test]
+fn reserved_meter_id_is_rejected() {
+    let output = tic2mqtt(&["--replay", &fixture("historique.tic"), "--meter-id", "status"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: invalid value 'status' for --meter-id: expected a name other than 'status', reserved for the bridge availability topic\n"
+    );
+
+    let config = write_config("reserved-id", &format!("[[meters]]\nid = \"status\"\nreplay = {:?}\n", fixture("historique.tic")));
+    let output = tic2mqtt(&["--config", &config]);
+    std::fs::remove_file(&config).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("reserved for the bridge availability topic"), "{}", String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,101 @@
+//! End-to-end tests of the SQLite output.
+
+mod common;
+
+use rusqlite::Connection;
+
+use common::{fixture, tic2mqtt};
+
+/// Path of a new database of a test
+fn database(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("tic2mqtt-{}-{}.db", name, std::process::id()));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    path.display().to_string()
+}
+
+fn remove(path: &str) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+}
+
+#[test]
+fn frames_and_minutes_are_stored() {
+    let path = database("sqlite");
+    let output = tic2mqtt(&["--replay", &fixture("historique.tic"), "--mode", "historique", "--outputs", "sqlite", "--sqlite-path", &path]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let conn = Connection::open(&path).unwrap();
+    let frames: Vec<(String, Option<String>, String)> = conn.prepare("SELECT meter, date, data FROM frames ORDER BY rowid").unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+        .collect::<Result<_, _>>().unwrap();
+    assert_eq!(frames.len(), 3);
+    for (meter, date, data) in &frames {
+        assert_eq!(meter, "031762001234");
+        assert_eq!(date, &None);
+        assert!(data.starts_with(r#"{"meter": "031762001234", "mode": "historique", "#), "{}", data);
+    }
+    assert!(frames[1].2.contains(r#""PAPP": 1351, "#), "{}", frames[1].2);
+
+    // The frames may fall on both sides of a minute
+    let (min, max, samples): (f64, f64, i64) = conn.query_row(
+        "SELECT min(min), max(max), sum(samples) FROM minutes WHERE meter = '031762001234' AND label = 'PAPP'", [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).unwrap();
+    assert_eq!((min, max, samples), (1351.0, 1511.0, 3));
+    let (avg, last): (f64, f64) = conn.query_row(
+        "SELECT avg, last FROM minutes WHERE label = 'TOTAL' ORDER BY time DESC LIMIT 1", [], |row| Ok((row.get(0)?, row.get(1)?)),
+    ).unwrap();
+    assert_eq!((avg, last), (10742683.0, 10742683.0));
+    // Text labels are not aggregated
+    let ptec: i64 = conn.query_row("SELECT count(*) FROM minutes WHERE label = 'PTEC'", [], |row| row.get(0)).unwrap();
+    assert_eq!(ptec, 0);
+    drop(conn);
+    remove(&path);
+}
+
+#[test]
+fn old_rows_are_purged() {
+    let path = database("sqlite-retention");
+    let args = ["--replay", &fixture("standard.tic"), "--mode", "standard", "--meter-id", "linky", "--sqlite-path", &path,
+        "--outputs", "sqlite", "--sqlite-retention", "1", "--sqlite-minute-retention", "30"];
+    let output = tic2mqtt(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Rows from 2 and 60 days ago
+    let conn = Connection::open(&path).unwrap();
+    let now: i64 = conn.query_row("SELECT max(time) FROM frames", [], |row| row.get(0)).unwrap();
+    for days in [2, 60] {
+        let time = now - days * 86_400_000;
+        conn.execute("INSERT INTO frames (meter, time, date, data) VALUES ('linky', ?1, NULL, '{}')", [time]).unwrap();
+        conn.execute("INSERT INTO minutes VALUES ('linky', ?1, 'EAST', 1, 1, 1, 1, 1)", [time]).unwrap();
+    }
+    drop(conn);
+
+    let output = tic2mqtt(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let conn = Connection::open(&path).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    // The frames of both runs
+    assert_eq!(count("SELECT count(*) FROM frames"), 4);
+    assert_eq!(count(&format!("SELECT count(*) FROM minutes WHERE label = 'EAST' AND time < {}", now - 86_400_000)), 1);
+    drop(conn);
+    remove(&path);
+}
+
+#[test]
+fn invalid_sqlite_settings_are_rejected() {
+    let output = tic2mqtt(&["--serial", "-", "--sqlite-path", "/nonexistent/tic2mqtt.db"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--sqlite-path"));
+
+    let output = tic2mqtt(&["--serial", "-", "--sqlite-path", "history.db", "--sqlite-retention", "-1"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--sqlite-retention"));
+
+    let output = tic2mqtt(&["--serial", "-", "--outputs", "mqtt,sqlite"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires --sqlite-path"));
+}